@@ -44,7 +44,7 @@ pub mod printer;
 pub mod sample;
 pub mod sav;
 pub mod sav_state;
-pub mod timeout;
+pub use pw_gtk_ext::timeout;
 #[macro_use]
 pub mod wrapper;
 
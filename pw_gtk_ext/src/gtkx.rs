@@ -7,17 +7,25 @@ pub mod value;
 #[macro_use]
 pub mod list_store;
 
+pub mod auto_update;
 pub mod buffered_list_store;
+pub mod buffered_tree_store;
 pub mod check_button;
+pub mod command_palette;
 pub mod combo_box_text;
 pub mod dialog;
 pub mod dialog_user;
 pub mod drawing_area;
 pub mod entry;
+pub mod filtered_list_store;
+pub mod header_bar;
 pub mod list;
+pub mod mapped;
 pub mod menu;
 pub mod notebook;
 pub mod paned;
 pub mod radio_button;
+pub mod recent_items;
+pub mod toolbar;
 pub mod tree_view;
 pub mod window;
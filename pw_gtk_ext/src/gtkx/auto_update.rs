@@ -0,0 +1,186 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Periodic updating of buffered stores while (and only while) their
+//! views are visible.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::gtk::prelude::*;
+use crate::gtkx::buffered_list_store::{
+    AsyncBufferedListStore, AsyncRowDataSource, BufferedListStore, RowDataSource,
+};
+use crate::gtkx::buffered_tree_store::{BufferedTreeStore, TreeDataSource};
+use crate::gtkx::mapped::{MapManagedUpdate, RequiredMapAction};
+use crate::timeout::ControlledTimeoutCycle;
+use crate::wrapper::*;
+
+/// A store whose contents can be brought up to date on demand.
+pub trait UpdatableStore {
+    fn repopulate(&self);
+    fn update(&self);
+}
+
+impl<R: RowDataSource> UpdatableStore for BufferedListStore<R> {
+    fn repopulate(&self) {
+        BufferedListStore::repopulate(self)
+    }
+
+    fn update(&self) {
+        BufferedListStore::update(self)
+    }
+}
+
+impl<R: AsyncRowDataSource + 'static> UpdatableStore for AsyncBufferedListStore<R> {
+    fn repopulate(&self) {
+        AsyncBufferedListStore::repopulate(self)
+    }
+
+    fn update(&self) {
+        AsyncBufferedListStore::update(self)
+    }
+}
+
+impl<T: TreeDataSource> UpdatableStore for BufferedTreeStore<T> {
+    fn repopulate(&self) {
+        BufferedTreeStore::repopulate(self)
+    }
+
+    fn update(&self) {
+        BufferedTreeStore::update(self)
+    }
+}
+
+pub struct AutoUpdaterCore<S: UpdatableStore> {
+    store: S,
+    is_mapped: Cell<bool>,
+    required_map_action: Cell<RequiredMapAction>,
+    cycle: Rc<ControlledTimeoutCycle>,
+    cb_id: Cell<u32>,
+}
+
+impl<S: UpdatableStore> Drop for AutoUpdaterCore<S> {
+    fn drop(&mut self) {
+        self.cycle.unregister_callback(self.cb_id.get());
+    }
+}
+
+/// Update `store` whenever `cycle` fires while `view` is mapped.  If the
+/// view is hidden at the time the update is deferred until it is next
+/// mapped.  The updates can be turned on and off via the cycle's check
+/// menu item.
+#[derive(WClone)]
+pub struct AutoUpdater<S: UpdatableStore>(Rc<AutoUpdaterCore<S>>);
+
+impl<S: UpdatableStore> MapManagedUpdate for AutoUpdater<S> {
+    fn do_renew(&self) {
+        self.0.store.repopulate()
+    }
+
+    fn do_update(&self) {
+        self.0.store.update()
+    }
+
+    fn is_mapped(&self) -> bool {
+        self.0.is_mapped.get()
+    }
+
+    fn set_is_mapped(&self, value: bool) {
+        self.0.is_mapped.set(value)
+    }
+
+    fn get_required_map_action(&self) -> RequiredMapAction {
+        self.0.required_map_action.get()
+    }
+
+    fn set_required_map_action(&self, action: RequiredMapAction) {
+        self.0.required_map_action.set(action)
+    }
+}
+
+impl<S: UpdatableStore + 'static> AutoUpdater<S> {
+    pub fn new<W: IsA<gtk::Widget>>(
+        store: S,
+        view: &W,
+        cycle: &Rc<ControlledTimeoutCycle>,
+    ) -> Self {
+        let auto_updater = Self(Rc::new(AutoUpdaterCore {
+            store,
+            is_mapped: Cell::new(view.get_mapped()),
+            required_map_action: Cell::new(RequiredMapAction::Nothing),
+            cycle: Rc::clone(cycle),
+            cb_id: Cell::new(0),
+        }));
+
+        let weak_core = Rc::downgrade(&auto_updater.0);
+        view.connect_map(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                AutoUpdater(core).on_map_action()
+            }
+        });
+        let weak_core = Rc::downgrade(&auto_updater.0);
+        view.connect_unmap(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                AutoUpdater(core).on_unmap_action()
+            }
+        });
+        let weak_core = Rc::downgrade(&auto_updater.0);
+        let cb_id = cycle.register_callback(Box::new(move || {
+            if let Some(core) = weak_core.upgrade() {
+                AutoUpdater(core).auto_update()
+            }
+        }));
+        auto_updater.0.cb_id.set(cb_id);
+
+        auto_updater
+    }
+
+    pub fn store(&self) -> &S {
+        &self.0.store
+    }
+
+    pub fn cycle(&self) -> &Rc<ControlledTimeoutCycle> {
+        &self.0.cycle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingStore(Rc<Cell<u32>>);
+
+    impl UpdatableStore for CountingStore {
+        fn repopulate(&self) {}
+
+        fn update(&self) {
+            self.0.set(self.0.get() + 1)
+        }
+    }
+
+    #[test]
+    fn dropped_auto_updaters_are_unregistered() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let updates = Rc::new(Cell::new(0));
+        let cycle = ControlledTimeoutCycle::new("Auto Update", false, 3600);
+        let view = gtk::Label::new(None);
+        let auto_updater = AutoUpdater::new(CountingStore(Rc::clone(&updates)), &view, &cycle);
+        assert_eq!(cycle.callback_count(), 1);
+
+        // the view isn't mapped so the update waits until it is
+        cycle.invoke_callbacks();
+        assert_eq!(updates.get(), 0);
+        auto_updater.on_map_action();
+        assert_eq!(updates.get(), 1);
+        cycle.invoke_callbacks();
+        assert_eq!(updates.get(), 2);
+
+        drop(auto_updater);
+        assert_eq!(cycle.callback_count(), 0);
+        cycle.invoke_callbacks();
+        assert_eq!(updates.get(), 2);
+    }
+}
@@ -1,13 +1,26 @@
 // Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
 
 use crate::glib::Value;
+use crate::glib::{ObjectExt, WeakRef};
+use crate::gtk::prelude::{TreeSelectionExt, TreeViewExt};
 pub use crate::gtkx::list_store::*;
-use std::cell::RefCell;
+use crate::wrapper::*;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
+use std::thread;
 
 pub trait RowDataSource: ListViewSpec + Sized {
     fn generate_rows(&self) -> Vec<Vec<Value>>;
     fn refresh(&self) -> Vec<u8>;
+
+    /// The column (if any) containing the value that uniquely
+    /// identifies a row.  Implementing this allows updates to preserve
+    /// the rows' order, the selection and the scroll position.
+    fn id_column() -> Option<i32> {
+        None
+    }
 }
 
 #[derive(Default)]
@@ -63,6 +76,7 @@ impl<R: RowDataSource> RowBuffer<R> {
 pub struct BufferedListStore<R: RowDataSource> {
     list_store: gtk::ListStore,
     row_buffer: RowBuffer<R>,
+    view: RefCell<Option<WeakRef<gtk::TreeView>>>,
 }
 
 impl<R: RowDataSource> BufferedListStore<R> {
@@ -72,6 +86,7 @@ impl<R: RowDataSource> BufferedListStore<R> {
         Self {
             list_store,
             row_buffer,
+            view: RefCell::new(None),
         }
     }
 
@@ -79,17 +94,126 @@ impl<R: RowDataSource> BufferedListStore<R> {
         &self.row_buffer.row_data_source
     }
 
+    /// Make `update()` and `repopulate()` keep the first visible row of
+    /// `tree_view` in view and `repopulate()` preserve the rows selected
+    /// in it (by id).  Only effective if the data source has an id column.
+    pub fn attach_view(&self, tree_view: &gtk::TreeView) {
+        *self.view.borrow_mut() = Some(tree_view.downgrade());
+    }
+
+    fn attached_view(&self) -> Option<gtk::TreeView> {
+        self.view.borrow().as_ref().and_then(|view| view.upgrade())
+    }
+
     pub fn repopulate(&self) {
         self.row_buffer.init();
-        self.list_store.repopulate_with(&self.row_buffer.get_rows());
+        match (R::id_column(), self.attached_view()) {
+            (Some(id_column), Some(tree_view)) => {
+                let selection = tree_view.get_selection();
+                let (paths, _) = selection.get_selected_rows();
+                let selected_keys: HashSet<RowKey> = paths
+                    .iter()
+                    .filter_map(|path| self.list_store.get_iter(path))
+                    .filter_map(|iter| self.row_key(&iter, id_column))
+                    .collect();
+                let anchor = self.first_visible_key(id_column, &tree_view);
+                self.list_store.repopulate_with(&self.row_buffer.get_rows());
+                self.select_keys(id_column, &selection, &selected_keys);
+                if let Some(key) = anchor {
+                    self.scroll_to_key(id_column, &tree_view, &key);
+                }
+            }
+            _ => self.list_store.repopulate_with(&self.row_buffer.get_rows()),
+        }
     }
 
+    /// Bring the store into line with the data source.  If the data
+    /// source has an id column the rows end up in the order given by
+    /// `RowDataSource::generate_rows()` with surviving rows updated in
+    /// place (so that the attached view's selection is unaffected and
+    /// its scroll position is preserved).  Otherwise, unwanted rows are
+    /// removed and new rows appended.
     pub fn update(&self) {
-        if !self.row_buffer.is_current() {
-            // this does a raw data update
-            self.row_buffer.finalise();
-            self.list_store.update_with(&self.row_buffer.get_rows());
-        };
+        if self.row_buffer.is_current() {
+            return;
+        }
+        match (R::id_column(), self.attached_view()) {
+            (Some(id_column), Some(tree_view)) => {
+                let anchor = self.first_visible_key(id_column, &tree_view);
+                self.update_rows();
+                if let Some(key) = anchor {
+                    self.scroll_to_key(id_column, &tree_view, &key);
+                }
+            }
+            _ => self.update_rows(),
+        }
+    }
+
+    fn update_rows(&self) {
+        // this does a raw data update
+        self.row_buffer.finalise();
+        match R::id_column() {
+            Some(id_column) => self
+                .list_store
+                .update_with_keyed(&self.row_buffer.get_rows(), id_column as usize),
+            None => self.list_store.update_with(&self.row_buffer.get_rows()),
+        }
+    }
+
+    fn row_key(&self, iter: &gtk::TreeIter, id_column: i32) -> Option<RowKey> {
+        RowKey::from_value(&self.list_store.get_value(iter, id_column))
+    }
+
+    fn first_visible_key(&self, id_column: i32, tree_view: &gtk::TreeView) -> Option<RowKey> {
+        let (start, _) = tree_view.get_visible_range()?;
+        let iter = self.list_store.get_iter(&start)?;
+        self.row_key(&iter, id_column)
+    }
+
+    // NB: scroll_to_cell() copes with the view not having been laid out
+    // yet since the update
+    fn scroll_to_key(&self, id_column: i32, tree_view: &gtk::TreeView, key: &RowKey) {
+        if let Some(iter) = self.list_store.get_iter_first() {
+            loop {
+                if self.row_key(&iter, id_column).as_ref() == Some(key) {
+                    let path = self.list_store.get_path(&iter);
+                    tree_view.scroll_to_cell(
+                        path.as_ref(),
+                        None::<&gtk::TreeViewColumn>,
+                        false,
+                        0.0,
+                        0.0,
+                    );
+                    return;
+                }
+                if !self.list_store.iter_next(&iter) {
+                    return;
+                }
+            }
+        }
+    }
+
+    // Only rows whose selection state is wrong are (un)selected so as
+    // to minimise the number of "changed" signals emitted.
+    fn select_keys(&self, id_column: i32, selection: &gtk::TreeSelection, keys: &HashSet<RowKey>) {
+        if let Some(iter) = self.list_store.get_iter_first() {
+            loop {
+                let wanted = match self.row_key(&iter, id_column) {
+                    Some(key) => keys.contains(&key),
+                    None => false,
+                };
+                if wanted != selection.iter_is_selected(&iter) {
+                    if wanted {
+                        selection.select_iter(&iter);
+                    } else {
+                        selection.unselect_iter(&iter);
+                    }
+                }
+                if !self.list_store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
     }
 }
 
@@ -102,3 +226,224 @@ impl<R: RowDataSource> WrappedTreeModel<gtk::ListStore> for BufferedListStore<R>
         &self.list_store
     }
 }
+
+/// A `RowDataSource` whose (possibly slow) data gathering is done on a
+/// worker thread.  The data is gathered as plain Rust data (which is sent
+/// back to the main thread) as `glib::Value`s can't be sent between threads.
+pub trait AsyncRowDataSource: ListViewSpec + Sized {
+    type Data: Send + 'static;
+
+    /// A closure that gathers the data and its digest.  It is run on a
+    /// worker thread so it should capture only what it needs to do the job.
+    fn data_generator(&self) -> Box<dyn FnOnce() -> (Vec<u8>, Self::Data) + Send>;
+
+    /// Convert the gathered data into rows (on the main thread).
+    fn rows_from_data(&self, data: Self::Data) -> Vec<Vec<Value>>;
+
+    /// The column (if any) containing the value that uniquely
+    /// identifies a row (see `RowDataSource::id_column()`).
+    fn id_column() -> Option<i32> {
+        None
+    }
+}
+
+type BusyCallback = Box<dyn Fn(bool)>;
+
+// What to do when the request in flight finishes.
+#[derive(Debug, PartialEq)]
+enum RequestOutcome {
+    // Use the result (repopulating the store if the flag is set).
+    Use(bool),
+    // Discard the (stale) result and start this merged request.
+    Restart(bool),
+}
+
+// Keeps at most one request in flight (the flags say whether the requests
+// are repopulations) and merges those made meanwhile into one pending one.
+#[derive(Debug, Default)]
+struct RequestQueue {
+    in_flight: Option<bool>,
+    pending: Option<bool>,
+}
+
+impl RequestQueue {
+    // Whether the request should be started now.
+    fn request(&mut self, repopulate: bool) -> bool {
+        if self.in_flight.is_some() {
+            self.pending = Some(self.pending.unwrap_or(false) || repopulate);
+            false
+        } else {
+            self.in_flight = Some(repopulate);
+            true
+        }
+    }
+
+    fn finished(&mut self) -> RequestOutcome {
+        let in_flight = self.in_flight.take().unwrap_or(false);
+        match self.pending.take() {
+            Some(pending) => {
+                let repopulate = in_flight || pending;
+                self.in_flight = Some(repopulate);
+                RequestOutcome::Restart(repopulate)
+            }
+            None => RequestOutcome::Use(in_flight),
+        }
+    }
+}
+
+pub struct AsyncBufferedListStoreCore<R: AsyncRowDataSource> {
+    list_store: gtk::ListStore,
+    row_data_source: R,
+    digest: RefCell<Vec<u8>>,
+    requests: RefCell<RequestQueue>,
+    busy: Cell<bool>,
+    busy_callbacks: RefCell<Vec<BusyCallback>>,
+}
+
+/// The asynchronous equivalent of `BufferedListStore`.  Only one request
+/// is in progress at a time: those made meanwhile are merged into a
+/// single request that is started (in place of using the now stale
+/// result) when the current one finishes.
+#[derive(WClone)]
+pub struct AsyncBufferedListStore<R: AsyncRowDataSource>(Rc<AsyncBufferedListStoreCore<R>>);
+
+impl<R: AsyncRowDataSource + 'static> AsyncBufferedListStore<R> {
+    pub fn new(row_data_source: R) -> Self {
+        let list_store = gtk::ListStore::new(&R::column_types());
+        Self(Rc::new(AsyncBufferedListStoreCore {
+            list_store,
+            row_data_source,
+            digest: RefCell::new(vec![]),
+            requests: RefCell::new(RequestQueue::default()),
+            busy: Cell::new(false),
+            busy_callbacks: RefCell::new(vec![]),
+        }))
+    }
+
+    pub fn row_data_source(&self) -> &R {
+        &self.0.row_data_source
+    }
+
+    /// Whether a request is in progress.
+    pub fn is_busy(&self) -> bool {
+        self.0.busy.get()
+    }
+
+    /// Call `callback` when a request starts (with `true`) and when the
+    /// last outstanding request is finished (with `false`) e.g. to show
+    /// a spinner or a busy cursor in the view.
+    pub fn connect_busy_changed<F: Fn(bool) + 'static>(&self, callback: F) {
+        self.0.busy_callbacks.borrow_mut().push(Box::new(callback));
+    }
+
+    fn set_busy(&self, busy: bool) {
+        if self.0.busy.get() != busy {
+            self.0.busy.set(busy);
+            for callback in self.0.busy_callbacks.borrow().iter() {
+                callback(busy)
+            }
+        }
+    }
+
+    pub fn repopulate(&self) {
+        self.request(true);
+    }
+
+    pub fn update(&self) {
+        self.request(false);
+    }
+
+    fn request(&self, repopulate: bool) {
+        let start = self.0.requests.borrow_mut().request(repopulate);
+        if start {
+            self.start();
+        }
+    }
+
+    fn start(&self) {
+        self.set_busy(true);
+        let generator = self.0.row_data_source.data_generator();
+        let (sender, receiver) = glib::MainContext::channel(glib::PRIORITY_DEFAULT);
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(generator));
+            // NB: the receiver may have gone away with the store
+            let _ = sender.send(result.ok());
+        });
+        let weak_core = Rc::downgrade(&self.0);
+        receiver.attach(None, move |o_result| {
+            if let Some(core) = weak_core.upgrade() {
+                let store = Self(core);
+                let outcome = store.0.requests.borrow_mut().finished();
+                match outcome {
+                    RequestOutcome::Use(repopulate) => {
+                        match o_result {
+                            Some((digest, data)) => store.finish(repopulate, digest, data),
+                            None => log::error!("AsyncBufferedListStore: data generator panicked"),
+                        }
+                        store.set_busy(false);
+                    }
+                    RequestOutcome::Restart(_) => store.start(),
+                }
+            }
+            glib::Continue(false)
+        });
+    }
+
+    fn finish(&self, repopulate: bool, digest: Vec<u8>, data: R::Data) {
+        if !repopulate && *self.0.digest.borrow() == digest {
+            return;
+        }
+        let rows = self.0.row_data_source.rows_from_data(data);
+        if repopulate {
+            self.0.list_store.repopulate_with(&rows);
+        } else {
+            match R::id_column() {
+                Some(id_column) => self
+                    .0
+                    .list_store
+                    .update_with_keyed(&rows, id_column as usize),
+                None => self.0.list_store.update_with(&rows),
+            }
+        }
+        *self.0.digest.borrow_mut() = digest;
+    }
+}
+
+impl<R: AsyncRowDataSource> WrappedTreeModel<gtk::ListStore> for AsyncBufferedListStore<R> {
+    fn columns() -> Vec<gtk::TreeViewColumn> {
+        R::columns()
+    }
+
+    fn model(&self) -> &gtk::ListStore {
+        &self.0.list_store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_queue_coalesces_requests() {
+        let mut requests = RequestQueue::default();
+        assert!(requests.request(false));
+        assert!(!requests.request(false));
+        assert!(!requests.request(true));
+        assert!(!requests.request(false));
+        assert_eq!(requests.finished(), RequestOutcome::Restart(true));
+        assert_eq!(requests.finished(), RequestOutcome::Use(true));
+        assert!(requests.request(false));
+        assert_eq!(requests.finished(), RequestOutcome::Use(false));
+    }
+
+    #[test]
+    fn request_queue_keeps_in_flight_repopulation() {
+        let mut requests = RequestQueue::default();
+        assert!(requests.request(true));
+        assert!(!requests.request(false));
+        assert_eq!(requests.finished(), RequestOutcome::Restart(true));
+        assert!(!requests.request(false));
+        assert_eq!(requests.finished(), RequestOutcome::Restart(true));
+        assert_eq!(requests.finished(), RequestOutcome::Use(true));
+    }
+}
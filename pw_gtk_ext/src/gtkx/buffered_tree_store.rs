@@ -0,0 +1,407 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! A tree store whose rows are generated (one level at a time) by a
+//! `TreeDataSource`.  A row's children aren't generated until the row is
+//! first expanded and updates only touch the subtrees whose digests have
+//! changed.
+
+use crate::glib::Value;
+use crate::gtk::prelude::{TreeModelExt, TreeStoreExt, TreeStoreExtManual, TreeViewExt};
+pub use crate::gtkx::list_store::ListViewSpec;
+pub use crate::gtkx::tree_model::*;
+use crate::wrapper::*;
+use crate::{are_eq_values, are_equal_as, UNEXPECTED};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// NB: the values in the id column must be unique across the whole tree.
+pub trait TreeDataSource: ListViewSpec + Sized {
+    /// Bring the raw data up to date and return a digest of the whole tree.
+    fn refresh(&self) -> Vec<u8>;
+
+    /// The rows immediately below the row identified by `parent` (or the
+    /// top level rows if `parent` is `None`) each paired with a flag
+    /// saying whether the row has children.
+    fn generate_rows(&self, parent: Option<&RowKey>) -> Vec<(Vec<Value>, bool)>;
+
+    /// A digest of the subtree below the row identified by `parent`.
+    fn subtree_digest(&self, parent: &RowKey) -> Vec<u8>;
+
+    /// The column containing the value that uniquely identifies a row.
+    fn id_column() -> i32 {
+        0
+    }
+}
+
+pub struct BufferedTreeStoreCore<T: TreeDataSource> {
+    tree_store: gtk::TreeStore,
+    tree_data_source: T,
+    // Only rows whose children have been generated have an entry.
+    // The entry for `None` is the digest of the whole tree.
+    digests: RefCell<HashMap<Option<RowKey>, Vec<u8>>>,
+}
+
+#[derive(WClone)]
+pub struct BufferedTreeStore<T: TreeDataSource>(Rc<BufferedTreeStoreCore<T>>);
+
+impl<T: TreeDataSource> BufferedTreeStore<T> {
+    pub fn new(tree_data_source: T) -> Self {
+        let tree_store = gtk::TreeStore::new(&T::column_types());
+        Self(Rc::new(BufferedTreeStoreCore {
+            tree_store,
+            tree_data_source,
+            digests: RefCell::new(HashMap::new()),
+        }))
+    }
+
+    pub fn tree_data_source(&self) -> &T {
+        &self.0.tree_data_source
+    }
+
+    fn row_key(&self, iter: &gtk::TreeIter) -> Option<RowKey> {
+        RowKey::from_value(&self.0.tree_store.get_value(iter, T::id_column()))
+    }
+
+    fn is_populated(&self, key: &RowKey) -> bool {
+        self.0.digests.borrow().contains_key(&Some(key.clone()))
+    }
+
+    // Rows with children get a place holder child (so that they can be
+    // expanded) until they are populated.
+    fn append_row(
+        &self,
+        parent: Option<&gtk::TreeIter>,
+        row: &[Value],
+        has_children: bool,
+    ) -> gtk::TreeIter {
+        let iter = self.0.tree_store.append(parent);
+        for (column, value) in row.iter().enumerate() {
+            self.0.tree_store.set_value(&iter, column as u32, value);
+        }
+        if has_children {
+            self.0.tree_store.append(Some(&iter));
+        }
+        iter
+    }
+
+    fn remove_children(&self, iter: &gtk::TreeIter) {
+        while let Some(child_iter) = self.0.tree_store.iter_children(Some(iter)) {
+            self.0.tree_store.remove(&child_iter);
+        }
+    }
+
+    // A row's place holder child has unset columns so its key (e.g. 0
+    // for an integer id column) could clash with that of a real row.
+    fn is_place_holder(&self, iter: &gtk::TreeIter) -> bool {
+        match self.0.tree_store.iter_parent(iter) {
+            Some(parent_iter) => match self.row_key(&parent_iter) {
+                Some(parent_key) => !self.is_populated(&parent_key),
+                None => true,
+            },
+            None => false,
+        }
+    }
+
+    // Drop the digests for the row at `iter` and its descendants.
+    // NB: only populated rows (which have no place holder) have
+    // descendants with digests.
+    fn forget_subtree(&self, iter: &gtk::TreeIter) {
+        match self.row_key(iter) {
+            Some(key) => {
+                if self.0.digests.borrow_mut().remove(&Some(key)).is_none() {
+                    return;
+                }
+            }
+            None => return,
+        }
+        if let Some(child_iter) = self.0.tree_store.iter_children(Some(iter)) {
+            loop {
+                self.forget_subtree(&child_iter);
+                if !self.0.tree_store.iter_next(&child_iter) {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn repopulate(&self) {
+        let digest = self.0.tree_data_source.refresh();
+        self.0.tree_store.clear();
+        {
+            let mut digests = self.0.digests.borrow_mut();
+            digests.clear();
+            digests.insert(None, digest);
+        }
+        for (row, has_children) in self.0.tree_data_source.generate_rows(None).iter() {
+            self.append_row(None, row, *has_children);
+        }
+    }
+
+    /// Generate the children of the row at `iter` if that hasn't
+    /// already been done.
+    pub fn populate_row(&self, iter: &gtk::TreeIter) {
+        let key = match self.row_key(iter) {
+            Some(key) => key,
+            None => return,
+        };
+        if self.is_populated(&key) {
+            return;
+        }
+        let o_place_holder = self.0.tree_store.iter_children(Some(iter));
+        let digest = self.0.tree_data_source.subtree_digest(&key);
+        for (row, has_children) in self.0.tree_data_source.generate_rows(Some(&key)).iter() {
+            self.append_row(Some(iter), row, *has_children);
+        }
+        if let Some(place_holder) = o_place_holder {
+            self.0.tree_store.remove(&place_holder);
+        }
+        self.0.digests.borrow_mut().insert(Some(key), digest);
+    }
+
+    /// Bring the store into line with the data source.  Only populated
+    /// subtrees whose digest has changed are regenerated and surviving
+    /// rows are updated (and moved) in place so that the view's
+    /// expansion and selection states are preserved.
+    pub fn update(&self) {
+        let digest = self.0.tree_data_source.refresh();
+        if self.0.digests.borrow().get(&None) == Some(&digest) {
+            return;
+        }
+        self.update_children(None, None);
+        self.0.digests.borrow_mut().insert(None, digest);
+    }
+
+    fn update_subtree(&self, key: &RowKey, iter: &gtk::TreeIter) {
+        let digest = self.0.tree_data_source.subtree_digest(key);
+        if self.0.digests.borrow().get(&Some(key.clone())) == Some(&digest) {
+            return;
+        }
+        self.update_children(Some(key), Some(iter));
+        self.0
+            .digests
+            .borrow_mut()
+            .insert(Some(key.clone()), digest);
+    }
+
+    fn update_children(&self, parent_key: Option<&RowKey>, parent_iter: Option<&gtk::TreeIter>) {
+        let store = &self.0.tree_store;
+        let rows = self.0.tree_data_source.generate_rows(parent_key);
+        let id_column = T::id_column() as usize;
+        let wanted_keys: Vec<Option<RowKey>> = rows
+            .iter()
+            .map(|(row, _)| RowKey::from_value(&row[id_column]))
+            .collect();
+        let mut present: Vec<(gtk::TreeIter, Option<RowKey>)> = vec![];
+        if let Some(iter) = store.iter_children(parent_iter) {
+            loop {
+                let o_key = if self.is_place_holder(&iter) {
+                    None
+                } else {
+                    self.row_key(&iter)
+                };
+                present.push((iter.clone(), o_key));
+                if !store.iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        let present_keys: Vec<Option<RowKey>> =
+            present.iter().map(|(_, o_key)| o_key.clone()).collect();
+        let (matches, order) = children_diff(&wanted_keys, &present_keys);
+        // Remove unwanted rows and update the others in place
+        let mut iters: HashMap<usize, gtk::TreeIter> = HashMap::with_capacity(rows.len());
+        let mut kept: HashSet<RowKey> = HashSet::with_capacity(rows.len());
+        for ((iter, o_key), o_index) in present.into_iter().zip(matches) {
+            match (o_key, o_index) {
+                (Some(key), Some(index)) => {
+                    let (row, has_children) = &rows[index];
+                    for (column, item) in row.iter().enumerate() {
+                        let value = store.get_value(&iter, column as i32);
+                        if !are_eq_values!(item, value) {
+                            store.set_value(&iter, column as u32, item);
+                        }
+                    }
+                    if !has_children {
+                        self.forget_subtree(&iter);
+                        self.remove_children(&iter);
+                    } else if self.is_populated(&key) {
+                        self.update_subtree(&key, &iter);
+                    } else if store.iter_n_children(Some(&iter)) == 0 {
+                        store.append(Some(&iter));
+                    }
+                    kept.insert(key);
+                    iters.insert(index, iter);
+                }
+                (o_key, _) => {
+                    // NB: a duplicate's digests belong to the row that was
+                    // kept and place holders (which have no key) have none
+                    if o_key.map_or(false, |key| !kept.contains(&key)) {
+                        self.forget_subtree(&iter);
+                    }
+                    store.remove(&iter);
+                }
+            }
+        }
+        // Add the new rows and then put everything in the right order
+        let mut previous: Option<gtk::TreeIter> = None;
+        for index in order {
+            let iter = match iters.remove(&index) {
+                Some(iter) => iter,
+                None => {
+                    let (row, has_children) = &rows[index];
+                    self.append_row(parent_iter, row, *has_children)
+                }
+            };
+            let o_next = match &previous {
+                Some(previous) => {
+                    let next = previous.clone();
+                    if store.iter_next(&next) {
+                        Some(next)
+                    } else {
+                        None
+                    }
+                }
+                None => store.iter_children(parent_iter),
+            };
+            if o_next.and_then(|next| self.row_key(&next)) != wanted_keys[index] {
+                store.move_after(&iter, previous.as_ref());
+            }
+            previous = Some(iter);
+        }
+    }
+}
+
+// For each of the `present` rows (identified by their keys) the index of
+// the wanted row that it becomes (or `None` if it's to be removed) and the
+// indices of the wanted rows in the order that they should end up in.
+// Only the first of any wanted (or present) rows with the same key is
+// used and rows with invalid keys (e.g. place holders) are never matched.
+fn children_diff(
+    wanted_keys: &[Option<RowKey>],
+    present_keys: &[Option<RowKey>],
+) -> (Vec<Option<usize>>, Vec<usize>) {
+    let mut wanted: HashMap<&RowKey, usize> = HashMap::with_capacity(wanted_keys.len());
+    let mut order = Vec::with_capacity(wanted_keys.len());
+    for (index, o_key) in wanted_keys.iter().enumerate() {
+        match o_key {
+            Some(key) => {
+                if *wanted.entry(key).or_insert(index) == index {
+                    order.push(index);
+                } else {
+                    log::warn!("BufferedTreeStore: duplicate key in row {}", index);
+                }
+            }
+            None => log::warn!("BufferedTreeStore: invalid key in row {}", index),
+        }
+    }
+    let mut matched: HashSet<&RowKey> = HashSet::with_capacity(present_keys.len());
+    let matches = present_keys
+        .iter()
+        .map(|o_key| {
+            let key = o_key.as_ref()?;
+            let index = *wanted.get(key)?;
+            if matched.insert(key) {
+                Some(index)
+            } else {
+                None
+            }
+        })
+        .collect();
+    (matches, order)
+}
+
+impl<T: TreeDataSource + 'static> BufferedTreeStore<T> {
+    /// Generate rows' children on demand as they're expanded in `tree_view`.
+    pub fn connect_lazy_population(&self, tree_view: &gtk::TreeView) {
+        let weak_core = Rc::downgrade(&self.0);
+        tree_view.connect_test_expand_row(move |_, iter, _| {
+            if let Some(core) = weak_core.upgrade() {
+                BufferedTreeStore(core).populate_row(iter);
+            }
+            gtk::Inhibit(false)
+        });
+    }
+}
+
+impl<T: TreeDataSource> WrappedTreeModel<gtk::TreeStore> for BufferedTreeStore<T> {
+    fn columns() -> Vec<gtk::TreeViewColumn> {
+        T::columns()
+    }
+
+    fn model(&self) -> &gtk::TreeStore {
+        &self.0.tree_store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(ids: &[i64]) -> Vec<Option<RowKey>> {
+        ids.iter().map(|id| Some(RowKey::Int(*id))).collect()
+    }
+
+    #[test]
+    fn children_diff_reorders_rows() {
+        assert_eq!(
+            children_diff(&keys(&[3, 1, 2]), &keys(&[1, 2, 3])),
+            (vec![Some(1), Some(2), Some(0)], vec![0, 1, 2])
+        );
+    }
+
+    #[test]
+    fn children_diff_inserts_rows() {
+        assert_eq!(
+            children_diff(&keys(&[1, 4, 2, 5]), &keys(&[1, 2])),
+            (vec![Some(0), Some(2)], vec![0, 1, 2, 3])
+        );
+    }
+
+    #[test]
+    fn children_diff_removes_rows() {
+        assert_eq!(
+            children_diff(&keys(&[2]), &keys(&[1, 2, 3])),
+            (vec![None, Some(0), None], vec![0])
+        );
+        assert_eq!(children_diff(&[], &keys(&[1])), (vec![None], vec![]));
+    }
+
+    #[test]
+    fn children_diff_uses_first_of_duplicates() {
+        // wanted duplicates
+        assert_eq!(
+            children_diff(&keys(&[1, 2, 1]), &keys(&[1])),
+            (vec![Some(0)], vec![0, 1])
+        );
+        // present duplicates
+        assert_eq!(
+            children_diff(&keys(&[1, 2]), &keys(&[2, 1, 2])),
+            (vec![Some(1), Some(0), None], vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn children_diff_never_matches_place_holders() {
+        // e.g. the place holder child of a row with an unset integer id
+        // that would otherwise read back as 0
+        let mut wanted_keys = keys(&[0, 1]);
+        wanted_keys.push(None);
+        assert_eq!(
+            children_diff(&wanted_keys, &[None]),
+            (vec![None], vec![0, 1])
+        );
+    }
+
+    #[test]
+    fn children_diff_applies_to_each_level() {
+        // the top level and the children of its row 10 (ids are unique
+        // across the whole tree)
+        let (top_matches, top_order) = children_diff(&keys(&[20, 10]), &keys(&[10, 30]));
+        assert_eq!((top_matches, top_order), (vec![Some(1), None], vec![0, 1]));
+        assert_eq!(
+            children_diff(&keys(&[12, 11]), &keys(&[11, 13])),
+            (vec![Some(1), None], vec![0, 1])
+        );
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! A searchable list of the (currently enabled) items in one or more
+//! `ManagedMenu`s from which the user can activate an operation.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use crate::gtkx::menu::{ManagedMenu, MenuCommand};
+use crate::wrapper::*;
+
+/// Score how well `pattern` matches `text` ignoring case.  All of the
+/// characters in `pattern` must appear in `text` in the same order.
+/// Consecutive matches and matches at the start of words score higher.
+pub fn fuzzy_score(pattern: &str, text: &str) -> Option<i32> {
+    let text: Vec<char> = text.chars().flat_map(|c| c.to_lowercase()).collect();
+    let mut score = 0;
+    let mut index = 0;
+    let mut previous: Option<usize> = None;
+    for p_char in pattern.chars().flat_map(|c| c.to_lowercase()) {
+        if p_char.is_whitespace() {
+            continue;
+        }
+        let found = index + text[index..].iter().position(|c| *c == p_char)?;
+        score += 1;
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += 8;
+        }
+        if previous.map_or(false, |previous| previous + 1 == found) {
+            score += 4;
+        }
+        previous = Some(found);
+        index = found + 1;
+    }
+    Some(score)
+}
+
+type ActivationCallback = Box<dyn Fn(&MenuCommand)>;
+
+#[derive(PWO)]
+pub struct CommandPaletteCore {
+    v_box: gtk::Box,
+    entry: gtk::SearchEntry,
+    tree_view: gtk::TreeView,
+    list_store: gtk::ListStore,
+    menus: RefCell<Vec<ManagedMenu>>,
+    commands: RefCell<Vec<MenuCommand>>,
+    activation_callbacks: RefCell<Vec<ActivationCallback>>,
+    window: RefCell<Option<gtk::Window>>,
+}
+
+#[derive(PWO, Wrapper, WClone)]
+pub struct CommandPalette(Rc<CommandPaletteCore>);
+
+impl Default for CommandPalette {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandPalette {
+    const TITLES: [&'static str; 3] = ["Command", "Shortcut", "Description"];
+    const INDEX: i32 = 3;
+
+    pub fn new() -> Self {
+        let v_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let entry = gtk::SearchEntry::new();
+        v_box.pack_start(&entry, false, false, 0);
+        let list_store = gtk::ListStore::new(&[
+            glib::Type::String,
+            glib::Type::String,
+            glib::Type::String,
+            glib::Type::U32,
+        ]);
+        let tree_view = gtk::TreeViewBuilder::new()
+            .model(&list_store)
+            .headers_visible(false)
+            .enable_search(false)
+            .activate_on_single_click(true)
+            .build();
+        for (index, title) in Self::TITLES.iter().enumerate() {
+            let col = gtk::TreeViewColumnBuilder::new()
+                .title(title)
+                .resizable(true)
+                .build();
+            let cell = gtk::CellRendererTextBuilder::new().editable(false).build();
+            col.pack_start(&cell, false);
+            col.add_attribute(&cell, "text", index as i32);
+            tree_view.append_column(&col);
+        }
+        let scrolled_window = gtk::ScrolledWindow::new(
+            Option::<&gtk::Adjustment>::None,
+            Option::<&gtk::Adjustment>::None,
+        );
+        scrolled_window.add(&tree_view);
+        v_box.pack_start(&scrolled_window, true, true, 0);
+        v_box.show_all();
+
+        let palette = Self(Rc::new(CommandPaletteCore {
+            v_box,
+            entry,
+            tree_view,
+            list_store,
+            menus: RefCell::new(vec![]),
+            commands: RefCell::new(vec![]),
+            activation_callbacks: RefCell::new(vec![]),
+            window: RefCell::new(None),
+        }));
+
+        let weak_core = Rc::downgrade(&palette.0);
+        palette.0.entry.connect_search_changed(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                CommandPalette(core).filter()
+            }
+        });
+        let weak_core = Rc::downgrade(&palette.0);
+        palette.0.entry.connect_activate(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                CommandPalette(core).activate_selected()
+            }
+        });
+        let weak_core = Rc::downgrade(&palette.0);
+        palette
+            .0
+            .tree_view
+            .connect_row_activated(move |_, path, _| {
+                if let Some(core) = weak_core.upgrade() {
+                    CommandPalette(core).activate_at(path)
+                }
+            });
+
+        palette
+    }
+
+    /// Include the items in `menu` (and its submenus).
+    pub fn add_menu(&self, menu: &ManagedMenu) {
+        self.0.menus.borrow_mut().push(menu.clone());
+        self.refresh();
+    }
+
+    /// Call `callback` after a command has been activated.
+    pub fn connect_activated<F: Fn(&MenuCommand) + 'static>(&self, callback: F) {
+        self.0
+            .activation_callbacks
+            .borrow_mut()
+            .push(Box::new(callback));
+    }
+
+    /// Re-index the menus' items to pick up changes in their conditions.
+    /// NB: which commands are enabled is only determined here (and
+    /// `add_menu()` and `show_in_window()` call this) so this needs to
+    /// be called if the conditions change while the palette is shown.
+    pub fn refresh(&self) {
+        let mut commands = vec![];
+        for menu in self.0.menus.borrow().iter() {
+            commands.extend(menu.commands().into_iter().filter(|cmd| cmd.is_enabled));
+        }
+        *self.0.commands.borrow_mut() = commands;
+        self.filter();
+    }
+
+    fn filter(&self) {
+        let pattern = self.0.entry.get_text();
+        let commands = self.0.commands.borrow();
+        let mut matches: Vec<(i32, usize)> = commands
+            .iter()
+            .enumerate()
+            .filter_map(|(index, cmd)| {
+                let score = fuzzy_score(&pattern, &cmd.label).or_else(|| {
+                    let tooltip = cmd.tooltip.as_deref().unwrap_or("");
+                    fuzzy_score(&pattern, tooltip).map(|score| score / 2)
+                })?;
+                Some((score, index))
+            })
+            .collect();
+        matches.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then_with(|| commands[a.1].label.cmp(&commands[b.1].label))
+        });
+        self.0.list_store.clear();
+        for (_, index) in matches {
+            let cmd = &commands[index];
+            let accel_label = match &cmd.accelerator {
+                Some(accel) => {
+                    let (key, mods) = gtk::accelerator_parse(accel);
+                    gtk::accelerator_get_label(key, mods)
+                        .map(|label| label.to_string())
+                        .unwrap_or_default()
+                }
+                None => String::new(),
+            };
+            let tooltip = cmd.tooltip.clone().unwrap_or_default();
+            self.0.list_store.insert_with_values(
+                None,
+                &[0, 1, 2, Self::INDEX as u32],
+                &[
+                    &cmd.label as &dyn ToValue,
+                    &accel_label,
+                    &tooltip,
+                    &(index as u32),
+                ],
+            );
+        }
+        if let Some(iter) = self.0.list_store.get_iter_first() {
+            self.0.tree_view.get_selection().select_iter(&iter);
+        }
+    }
+
+    fn activate_at(&self, path: &gtk::TreePath) {
+        let o_index = self
+            .0
+            .list_store
+            .get_iter(path)
+            .and_then(|iter| {
+                self.0
+                    .list_store
+                    .get_value(&iter, Self::INDEX)
+                    .get::<u32>()
+                    .ok()
+            })
+            .flatten();
+        if let Some(index) = o_index {
+            let o_cmd = self.0.commands.borrow().get(index as usize).cloned();
+            if let Some(cmd) = o_cmd {
+                let o_window = self.0.window.borrow().clone();
+                if let Some(window) = o_window {
+                    window.close();
+                }
+                cmd.activate();
+                for callback in self.0.activation_callbacks.borrow().iter() {
+                    callback(&cmd)
+                }
+            }
+        }
+    }
+
+    pub fn activate_selected(&self) {
+        let (paths, _) = self.0.tree_view.get_selection().get_selected_rows();
+        if let Some(path) = paths.first() {
+            self.activate_at(path);
+        }
+    }
+
+    /// Show the palette in a window (transient for `parent`) that is
+    /// hidden when a command is activated or focus is lost.  Bind this
+    /// to e.g. "<Ctrl><Shift>P".  If the palette is already being shown
+    /// its window is presented to the user instead.
+    pub fn show_in_window(&self, parent: Option<&gtk::Window>) -> gtk::Window {
+        let o_window = self.0.window.borrow().clone();
+        if let Some(window) = o_window {
+            self.refresh();
+            window.present();
+            self.0.entry.grab_focus();
+            return window;
+        }
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_decorated(false);
+        window.set_modal(true);
+        window.set_transient_for(parent);
+        window.set_position(gtk::WindowPosition::CenterOnParent);
+        window.set_default_size(480, 320);
+        window.add(self.pwo());
+        window.connect_focus_out_event(|window, _| {
+            window.close();
+            gtk::Inhibit(false)
+        });
+        let weak_core = Rc::downgrade(&self.0);
+        window.connect_delete_event(move |window, _| {
+            if let Some(core) = weak_core.upgrade() {
+                window.remove(&core.v_box);
+                *core.window.borrow_mut() = None;
+            }
+            gtk::Inhibit(false)
+        });
+        window.connect_key_press_event(|window, event| {
+            if event.get_keyval() == gdk::keys::constants::Escape {
+                window.close();
+                gtk::Inhibit(true)
+            } else {
+                gtk::Inhibit(false)
+            }
+        });
+        *self.0.window.borrow_mut() = Some(window.clone());
+        self.0.entry.set_text("");
+        self.refresh();
+        window.show_all();
+        self.0.entry.grab_focus();
+        window
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_requires_ordered_subsequence() {
+        assert!(fuzzy_score("cpy", "Copy").is_some());
+        assert!(fuzzy_score("ypc", "Copy").is_none());
+        assert_eq!(fuzzy_score("", "Copy"), Some(0));
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_word_starts_and_runs() {
+        let word_starts = fuzzy_score("sa", "Select All").unwrap();
+        let mid_word = fuzzy_score("sa", "Unsaved").unwrap();
+        assert!(word_starts > mid_word);
+        let run = fuzzy_score("past", "Paste").unwrap();
+        let scattered = fuzzy_score("past", "Prepare a sheet").unwrap_or(0);
+        assert!(run > scattered);
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! A `WrappedListStore` viewed through a `gtk::TreeModelFilter` and a
+//! `gtk::TreeModelSort` with the filtering and sorting expressed in terms
+//! of typed rows.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::get_row_values_from;
+use crate::gtk::prelude::*;
+use crate::gtkx::list_store::{ListRow, ListViewSpec, WrappedListStore};
+use crate::gtkx::tree_model::WrappedTreeModel;
+use crate::wrapper::*;
+
+type RowFilter<L> = Box<dyn Fn(&L) -> bool>;
+type SearchMatcher<L> = Box<dyn Fn(&L, &str) -> bool>;
+
+struct FilterState<L: ListRow> {
+    row_filter: RefCell<Option<RowFilter<L>>>,
+    search_matcher: RefCell<Option<SearchMatcher<L>>>,
+    search_text: RefCell<String>,
+}
+
+impl<L: ListRow> FilterState<L> {
+    fn is_visible(&self, model: &gtk::TreeModel, iter: &gtk::TreeIter) -> bool {
+        let search_text = self.search_text.borrow();
+        let row_filter = self.row_filter.borrow();
+        if row_filter.is_none() && search_text.is_empty() {
+            return true;
+        }
+        let row = match L::from_row(&get_row_values_from!(model, iter)) {
+            Some(row) => row,
+            // e.g. a row that has been appended but not yet filled in
+            None => return false,
+        };
+        if let Some(row_filter) = row_filter.as_ref() {
+            if !row_filter(&row) {
+                return false;
+            }
+        }
+        if search_text.is_empty() {
+            return true;
+        }
+        match self.search_matcher.borrow().as_ref() {
+            Some(search_matcher) => search_matcher(&row, &search_text),
+            None => true,
+        }
+    }
+}
+
+pub struct FilteredSortedListStoreCore<L: ListViewSpec + ListRow> {
+    list_store: WrappedListStore<L>,
+    filter: gtk::TreeModelFilter,
+    sort: gtk::TreeModelSort,
+    filter_state: Rc<FilterState<L>>,
+}
+
+/// NB: the view should be given `sort_model()` as its model (which is
+/// what `TreeViewWithPopupBuilder::build()` does with this wrapper) and
+/// rows should be added, changed and removed via `list_store()`.
+#[derive(WClone)]
+pub struct FilteredSortedListStore<L: ListViewSpec + ListRow>(Rc<FilteredSortedListStoreCore<L>>);
+
+impl<L: ListViewSpec + ListRow + 'static> Default for FilteredSortedListStore<L> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<L: ListViewSpec + ListRow + 'static> FilteredSortedListStore<L> {
+    pub fn new() -> Self {
+        let list_store = WrappedListStore::<L>::new();
+        let filter = gtk::TreeModelFilter::new(&*list_store, None);
+        let filter_state = Rc::new(FilterState {
+            row_filter: RefCell::new(None),
+            search_matcher: RefCell::new(None),
+            search_text: RefCell::new(String::new()),
+        });
+        let filter_state_c = Rc::clone(&filter_state);
+        filter.set_visible_func(move |model, iter| filter_state_c.is_visible(model, iter));
+        let sort = gtk::TreeModelSort::new(&filter);
+        Self(Rc::new(FilteredSortedListStoreCore {
+            list_store,
+            filter,
+            sort,
+            filter_state,
+        }))
+    }
+
+    pub fn list_store(&self) -> &WrappedListStore<L> {
+        &self.0.list_store
+    }
+
+    pub fn sort_model(&self) -> &gtk::TreeModelSort {
+        &self.0.sort
+    }
+
+    /// Only show the rows for which `row_filter` returns `true`.
+    pub fn set_filter<F: Fn(&L) -> bool + 'static>(&self, row_filter: F) {
+        *self.0.filter_state.row_filter.borrow_mut() = Some(Box::new(row_filter));
+        self.0.filter.refilter();
+    }
+
+    pub fn clear_filter(&self) {
+        *self.0.filter_state.row_filter.borrow_mut() = None;
+        self.0.filter.refilter();
+    }
+
+    /// Re-evaluate the filter e.g. after something it depends on changed.
+    pub fn refilter(&self) {
+        self.0.filter.refilter();
+    }
+
+    /// Sort the rows using `compare` when the view's column with
+    /// `sort_column_id` is selected for sorting.
+    pub fn set_sort_func<F: Fn(&L, &L) -> Ordering + 'static>(
+        &self,
+        sort_column_id: u32,
+        compare: F,
+    ) {
+        self.0.sort.set_sort_func(
+            gtk::SortColumn::Index(sort_column_id),
+            move |model, iter1, iter2| {
+                let o_row1 = L::from_row(&get_row_values_from!(model, iter1));
+                let o_row2 = L::from_row(&get_row_values_from!(model, iter2));
+                match (o_row1, o_row2) {
+                    (Some(row1), Some(row2)) => compare(&row1, &row2),
+                    (Some(_), None) => Ordering::Less,
+                    (None, Some(_)) => Ordering::Greater,
+                    (None, None) => Ordering::Equal,
+                }
+            },
+        );
+    }
+
+    /// A search entry whose text is used (via `matches`) to filter the
+    /// rows in addition to any filter set with `set_filter()`.
+    pub fn new_search_entry<F: Fn(&L, &str) -> bool + 'static>(
+        &self,
+        matches: F,
+    ) -> gtk::SearchEntry {
+        *self.0.filter_state.search_matcher.borrow_mut() = Some(Box::new(matches));
+        let entry = gtk::SearchEntry::new();
+        let weak_core = Rc::downgrade(&self.0);
+        entry.connect_search_changed(move |entry| {
+            if let Some(core) = weak_core.upgrade() {
+                *core.filter_state.search_text.borrow_mut() = entry.get_text().to_string();
+                core.filter.refilter();
+            }
+        });
+        entry
+    }
+
+    /// Convert an iter in `sort_model()` (e.g. from the view's
+    /// selection) to the equivalent iter in `list_store()`.
+    pub fn list_store_iter(&self, sort_iter: &gtk::TreeIter) -> gtk::TreeIter {
+        let filter_iter = self.0.sort.convert_iter_to_child_iter(sort_iter);
+        self.0.filter.convert_iter_to_child_iter(&filter_iter)
+    }
+
+    /// Convert an iter in `list_store()` to the equivalent iter in
+    /// `sort_model()` (if the row is currently visible).
+    pub fn sort_model_iter(&self, list_store_iter: &gtk::TreeIter) -> Option<gtk::TreeIter> {
+        let filter_iter = self.0.filter.convert_child_iter_to_iter(list_store_iter)?;
+        self.0.sort.convert_child_iter_to_iter(&filter_iter)
+    }
+
+    /// The (typed) row at `sort_iter` in `sort_model()`.
+    pub fn get(&self, sort_iter: &gtk::TreeIter) -> Option<L> {
+        self.0.list_store.get(&self.list_store_iter(sort_iter))
+    }
+}
+
+impl<L: ListViewSpec + ListRow> WrappedTreeModel<gtk::TreeModelSort>
+    for FilteredSortedListStore<L>
+{
+    fn columns() -> Vec<gtk::TreeViewColumn> {
+        <L as ListViewSpec>::columns()
+    }
+
+    fn model(&self) -> &gtk::TreeModelSort {
+        &self.0.sort
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtkx::tree_model::underlying_model_iter;
+
+    #[derive(Debug, PartialEq)]
+    struct Row {
+        id: u32,
+        name: String,
+    }
+
+    impl ListViewSpec for Row {
+        fn column_types() -> Vec<glib::Type> {
+            vec![glib::Type::U32, glib::Type::String]
+        }
+
+        fn columns() -> Vec<gtk::TreeViewColumn> {
+            vec![]
+        }
+    }
+
+    impl ListRow for Row {
+        fn column_types() -> Vec<glib::Type> {
+            <Self as ListViewSpec>::column_types()
+        }
+
+        fn columns() -> Vec<gtk::TreeViewColumn> {
+            vec![]
+        }
+
+        fn to_row(&self) -> Vec<glib::Value> {
+            vec![self.id.to_value(), self.name.to_value()]
+        }
+
+        fn from_row(row: &[glib::Value]) -> Option<Self> {
+            Some(Self {
+                id: row[0].get::<u32>().ok()??,
+                name: row[1].get::<String>().ok()??,
+            })
+        }
+    }
+
+    fn visible_ids(store: &FilteredSortedListStore<Row>) -> Vec<u32> {
+        let mut ids = vec![];
+        if let Some(iter) = store.sort_model().get_iter_first() {
+            loop {
+                ids.push(store.get(&iter).unwrap().id);
+                if !store.sort_model().iter_next(&iter) {
+                    break;
+                }
+            }
+        }
+        ids
+    }
+
+    #[test]
+    fn rows_are_filtered_sorted_and_resolved() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let store = FilteredSortedListStore::<Row>::new();
+        for (id, name) in [(1, "b"), (2, "a"), (3, "c"), (4, "ab")].iter() {
+            store.list_store().append(&Row {
+                id: *id,
+                name: name.to_string(),
+            });
+        }
+        assert_eq!(visible_ids(&store), vec![1, 2, 3, 4]);
+
+        store.set_sort_func(1, |row1, row2| row1.name.cmp(&row2.name));
+        store
+            .sort_model()
+            .set_sort_column_id(gtk::SortColumn::Index(1), gtk::SortType::Ascending);
+        assert_eq!(visible_ids(&store), vec![2, 4, 1, 3]);
+
+        store.set_filter(|row| row.id != 4);
+        assert_eq!(visible_ids(&store), vec![2, 1, 3]);
+
+        // The view's ids are looked up in the underlying list store
+        let model = store.sort_model().clone().upcast::<gtk::TreeModel>();
+        let iter = model.get_iter_first().unwrap();
+        let (list_model, list_iter) = underlying_model_iter(&model, &iter);
+        assert_eq!(
+            &list_model,
+            store.list_store().upcast_ref::<gtk::TreeModel>()
+        );
+        assert_eq!(
+            list_model.get_value(&list_iter, 0).get::<u32>(),
+            Ok(Some(2))
+        );
+        assert_eq!(
+            store
+                .sort_model_iter(&list_iter)
+                .map(|iter| store.get(&iter)),
+            Some(store.get(&iter))
+        );
+
+        // Filtered out rows aren't in the sort model
+        let hidden_iter = store.list_store().iter_nth_child(None, 3).unwrap();
+        assert!(store.sort_model_iter(&hidden_iter).is_none());
+        store.clear_filter();
+        assert!(store.sort_model_iter(&hidden_iter).is_some());
+    }
+}
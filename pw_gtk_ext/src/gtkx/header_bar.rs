@@ -0,0 +1,226 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use crate::{
+    gtkx::menu::{owned_items, MenuItemSpec},
+    sav_state::{
+        self, ChangedCondnsNotifier, ConditionalWidgets, ConditionalWidgetsBuilder, MaskedCondns,
+        WidgetStatesControlled,
+    },
+    wrapper::*,
+};
+
+#[derive(PWO)]
+pub struct ManagedHeaderBarCore {
+    header_bar: gtk::HeaderBar,
+    items: ConditionalWidgets<String, gtk::Button>,
+    accel_group: gtk::AccelGroup,
+}
+
+/// A `gtk::HeaderBar` whose buttons are built from the same specs as a
+/// `ManagedMenu`'s and whose states are controlled by conditions.
+#[derive(PWO, WClone)]
+pub struct ManagedHeaderBar(Rc<ManagedHeaderBarCore>);
+
+impl ManagedHeaderBar {
+    pub fn header_bar(&self) -> gtk::HeaderBar {
+        self.0.header_bar.clone()
+    }
+
+    /// See `ManagedMenu::accel_group()`.
+    pub fn accel_group(&self) -> &gtk::AccelGroup {
+        &self.0.accel_group
+    }
+
+    pub fn button(&self, name: &str) -> Result<gtk::Button, sav_state::Error> {
+        self.0.items.get_widget(name)
+    }
+
+    // Buttons with an image show only the image (with the label as its
+    // tooltip if the spec doesn't provide one) to save space.
+    fn new_button(menu_item_spec: &MenuItemSpec) -> gtk::Button {
+        match menu_item_spec.image(gtk::IconSize::Button) {
+            Some(image) => {
+                let button = gtk::ButtonBuilder::new().image(&image).build();
+                let tooltip = match &menu_item_spec.2 {
+                    Some(tooltip) => tooltip.clone(),
+                    None => menu_item_spec.plain_label(),
+                };
+                button.set_tooltip_text(Some(&tooltip));
+                button
+            }
+            None => {
+                let button = gtk::Button::with_mnemonic(&menu_item_spec.0);
+                button.set_tooltip_text(menu_item_spec.2.as_deref());
+                button
+            }
+        }
+    }
+
+    // NB: the button is shown before its state is set (rather than
+    // showing the whole header bar after) so that it stays hidden if
+    // it should
+    fn new_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+    ) -> Result<gtk::Button, sav_state::Error> {
+        let button = Self::new_button(menu_item_spec);
+        button.show_all();
+        self.0.items.add_widget(name.to_string(), &button, condns)?;
+        if let Some(accel) = menu_item_spec.accelerator() {
+            self.0.items.add_accelerator(
+                name.to_string(),
+                &button,
+                "clicked",
+                &self.0.accel_group,
+                accel,
+            );
+        }
+
+        Ok(button)
+    }
+
+    pub fn pack_start_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+    ) -> Result<gtk::Button, sav_state::Error> {
+        let button = self.new_item(name, menu_item_spec, condns)?;
+        self.0.header_bar.pack_start(&button);
+
+        Ok(button)
+    }
+
+    pub fn pack_end_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+    ) -> Result<gtk::Button, sav_state::Error> {
+        let button = self.new_item(name, menu_item_spec, condns)?;
+        self.0.header_bar.pack_end(&button);
+
+        Ok(button)
+    }
+
+    pub fn remove_item(&self, name: &str) -> Result<gtk::Button, sav_state::Error> {
+        let button = self.0.items.remove_widget(name)?;
+        self.0.header_bar.remove(&button);
+        Ok(button)
+    }
+
+    pub fn update_condns(&self, changed_condns: MaskedCondns) {
+        self.0.items.update_condns(changed_condns);
+    }
+
+    pub fn update_hover_condns(&self, hover_ok: bool) {
+        self.0.items.update_hover_condns(hover_ok);
+    }
+}
+
+#[derive(Default)]
+pub struct ManagedHeaderBarBuilder {
+    conditional_widgets_builder: ConditionalWidgetsBuilder,
+    title: Option<String>,
+    subtitle: Option<String>,
+    show_close_button: bool,
+    accel_group: Option<gtk::AccelGroup>,
+    start_items: Vec<(String, MenuItemSpec, u64)>,
+    end_items: Vec<(String, MenuItemSpec, u64)>,
+}
+
+impl ManagedHeaderBarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn widget_states_controlled(
+        &mut self,
+        widget_states_controlled: WidgetStatesControlled,
+    ) -> &mut Self {
+        self.conditional_widgets_builder
+            .widget_states_controlled(widget_states_controlled);
+        self
+    }
+
+    pub fn selection(&mut self, selection: &gtk::TreeSelection) -> &mut Self {
+        self.conditional_widgets_builder.selection(selection);
+        self
+    }
+
+    pub fn change_notifier(&mut self, change_notifier: &ChangedCondnsNotifier) -> &mut Self {
+        self.conditional_widgets_builder
+            .change_notifier(change_notifier);
+        self
+    }
+
+    pub fn title(&mut self, title: &str) -> &mut Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    pub fn subtitle(&mut self, subtitle: &str) -> &mut Self {
+        self.subtitle = Some(subtitle.to_string());
+        self
+    }
+
+    pub fn show_close_button(&mut self, show_close_button: bool) -> &mut Self {
+        self.show_close_button = show_close_button;
+        self
+    }
+
+    /// See `ManagedMenuBuilder::accel_group()`.
+    pub fn accel_group(&mut self, accel_group: &gtk::AccelGroup) -> &mut Self {
+        self.accel_group = Some(accel_group.clone());
+        self
+    }
+
+    pub fn start_items(&mut self, items: &[(&str, MenuItemSpec, u64)]) -> &mut Self {
+        self.start_items = owned_items(items);
+        self
+    }
+
+    pub fn end_items(&mut self, items: &[(&str, MenuItemSpec, u64)]) -> &mut Self {
+        self.end_items = owned_items(items);
+        self
+    }
+
+    pub fn build(&self) -> ManagedHeaderBar {
+        let header_bar = gtk::HeaderBarBuilder::new()
+            .show_close_button(self.show_close_button)
+            .build();
+        header_bar.set_title(self.title.as_deref());
+        header_bar.set_subtitle(self.subtitle.as_deref());
+        header_bar.show();
+        let items = self
+            .conditional_widgets_builder
+            .build::<String, gtk::Button>();
+        let accel_group = match &self.accel_group {
+            Some(accel_group) => accel_group.clone(),
+            None => gtk::AccelGroup::new(),
+        };
+        let mhb = ManagedHeaderBar(Rc::new(ManagedHeaderBarCore {
+            header_bar,
+            items,
+            accel_group,
+        }));
+        for (name, menu_item_spec, condns) in self.start_items.iter() {
+            if let Err(err) = mhb.pack_start_item(name, menu_item_spec, *condns) {
+                panic!("Error adding item '{}' to header bar: {}", name, err);
+            };
+        }
+        for (name, menu_item_spec, condns) in self.end_items.iter() {
+            if let Err(err) = mhb.pack_end_item(name, menu_item_spec, *condns) {
+                panic!("Error adding item '{}' to header bar: {}", name, err);
+            };
+        }
+
+        mhb
+    }
+}
@@ -1,8 +1,10 @@
 // Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
 
 use crate::glib;
+use crate::gtk::prelude::{GtkListStoreExt, TreeModelExt};
 pub use crate::gtkx::tree_model::*;
 use crate::{are_eq_values, are_equal_as, get_row_values_from, matches_list_row, UNEXPECTED};
+use std::collections::{HashMap, HashSet};
 use std::marker::PhantomData;
 use std::ops::Deref;
 
@@ -132,7 +134,76 @@ pub trait ListRowOps:
         }
     }
 
-    // NB: this function assumes that all rows are unique and that order isn't important
+    /// Make the store's contents (and their order) match `rows` where
+    /// rows are identified by the value in their `key_column`.  Rows
+    /// that are still wanted are updated in place (only changed cells
+    /// are set) rather than being removed and re-added so that the
+    /// view's selection etc. are preserved.  The cost is dominated by
+    /// comparing each cell of the rows in the store with `rows` (plus an
+    /// O(log n) store operation per row removed or appended).
+    fn update_with_keyed(&self, rows: &[Vec<glib::Value>], key_column: usize) {
+        let row_keys: Vec<Option<RowKey>> = rows
+            .iter()
+            .map(|row| RowKey::from_value(&row[key_column]))
+            .collect();
+        // NB: the first of any rows with duplicate keys is the one used
+        let mut wanted: HashMap<&RowKey, usize> = HashMap::with_capacity(rows.len());
+        for (index, o_key) in row_keys.iter().enumerate() {
+            match o_key {
+                Some(key) => {
+                    if wanted.contains_key(key) {
+                        log::warn!("update_with_keyed(): duplicate key in row {}", index);
+                    } else {
+                        wanted.insert(key, index);
+                    }
+                }
+                None => log::warn!("update_with_keyed(): invalid key in row {}", index),
+            }
+        }
+        // Remove unwanted rows and update the others in place
+        let mut present: HashMap<RowKey, u32> = HashMap::with_capacity(rows.len());
+        if let Some(iter) = self.get_iter_first() {
+            while self.iter_is_valid(&iter) {
+                let value = self.get_value(&iter, key_column as i32);
+                let o_index = RowKey::from_value(&value).and_then(|key| {
+                    if present.contains_key(&key) {
+                        None
+                    } else {
+                        wanted.get(&key).map(|index| (key, *index))
+                    }
+                });
+                match o_index {
+                    Some((key, index)) => {
+                        for (column, item) in rows[index].iter().enumerate() {
+                            let value = self.get_value(&iter, column as i32);
+                            if !are_eq_values!(item, value) {
+                                self.set_value(&iter, column as u32, item);
+                            }
+                        }
+                        present.insert(key, present.len() as u32);
+                        self.iter_next(&iter);
+                    }
+                    // NB: this call updates the iter
+                    None => {
+                        self.remove(&iter);
+                    }
+                }
+            }
+        }
+        // Append the new rows and then put everything in the right order
+        let (new_order, to_append) = keyed_order(&row_keys, &present);
+        for index in to_append {
+            let row = &rows[index];
+            append_row_to_list!(row, self);
+        }
+        if new_order.iter().enumerate().any(|(i, p)| i as u32 != *p) {
+            self.reorder(&new_order);
+        }
+    }
+
+    // NB: this function assumes that all rows are unique and that order
+    // isn't important.  It is O(n^2) so use `update_with_keyed()` instead
+    // if the rows have a key (id) column.
     fn update_with(&self, rows: &[Vec<glib::Value>]) {
         // First remove the rows that have gone away
         if let Some(iter) = self.get_iter_first() {
@@ -164,11 +235,51 @@ pub trait ListRowOps:
 
 impl ListRowOps for gtk::ListStore {}
 
+// The order to pass to `reorder()` (once the new rows have been appended)
+// given the keys of the wanted rows and the positions of the rows that
+// are already `present`, and the indices of the rows that need to be
+// appended.  Rows with invalid keys or whose key has already been placed
+// are skipped so that each position appears exactly once.
+fn keyed_order(
+    row_keys: &[Option<RowKey>],
+    present: &HashMap<RowKey, u32>,
+) -> (Vec<u32>, Vec<usize>) {
+    let mut new_order = Vec::with_capacity(row_keys.len());
+    let mut to_append = vec![];
+    let mut placed: HashSet<&RowKey> = HashSet::with_capacity(row_keys.len());
+    let mut next_position = present.len() as u32;
+    for (index, o_key) in row_keys.iter().enumerate() {
+        if let Some(key) = o_key {
+            if !placed.insert(key) {
+                continue;
+            }
+            match present.get(key) {
+                Some(position) => new_order.push(*position),
+                None => {
+                    to_append.push(index);
+                    new_order.push(next_position);
+                    next_position += 1;
+                }
+            }
+        }
+    }
+    (new_order, to_append)
+}
+
 pub trait ListViewSpec {
     fn column_types() -> Vec<glib::Type>;
     fn columns() -> Vec<gtk::TreeViewColumn>;
 }
 
+/// A typed row of a list store.  Usually implemented (along with
+/// `ListViewSpec`) via `#[derive(ListRow)]`.
+pub trait ListRow: Sized {
+    fn column_types() -> Vec<glib::Type>;
+    fn columns() -> Vec<gtk::TreeViewColumn>;
+    fn to_row(&self) -> Vec<glib::Value>;
+    fn from_row(row: &[glib::Value]) -> Option<Self>;
+}
+
 #[derive(Clone)]
 pub struct WrappedListStore<L: ListViewSpec>(gtk::ListStore, PhantomData<L>);
 
@@ -201,3 +312,87 @@ impl<L: ListViewSpec> Default for WrappedListStore<L> {
         Self(gtk::ListStore::new(&L::column_types()), PhantomData)
     }
 }
+
+impl<L: ListViewSpec + ListRow> WrappedListStore<L> {
+    pub fn append(&self, row: &L) -> gtk::TreeIter {
+        self.0.append_row(&row.to_row())
+    }
+
+    pub fn get(&self, iter: &gtk::TreeIter) -> Option<L> {
+        L::from_row(&get_row_values_from!(self.0, iter))
+    }
+
+    /// NB: rows that can't be converted (e.g. rows that have been
+    /// appended but not yet filled in) are omitted (and logged).
+    pub fn rows(&self) -> Vec<L> {
+        self.0
+            .get_rows_values()
+            .iter()
+            .enumerate()
+            .filter_map(|(index, row)| {
+                let o_row = L::from_row(row);
+                if o_row.is_none() {
+                    log::warn!("WrappedListStore: omitting invalid row {}", index);
+                }
+                o_row
+            })
+            .collect()
+    }
+
+    pub fn repopulate(&self, rows: &[L]) {
+        self.0.clear();
+        for row in rows.iter() {
+            self.append(row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glib::ToValue;
+
+    fn key(id: &str) -> Option<RowKey> {
+        RowKey::from_value(&id.to_value())
+    }
+
+    fn present(ids: &[&str]) -> HashMap<RowKey, u32> {
+        ids.iter()
+            .enumerate()
+            .map(|(position, id)| (key(id).unwrap(), position as u32))
+            .collect()
+    }
+
+    #[test]
+    fn keyed_order_skips_duplicate_keys() {
+        let row_keys = [key("a"), key("b"), key("a"), key("b")];
+        assert_eq!(
+            keyed_order(&row_keys, &present(&[])),
+            (vec![0, 1], vec![0, 1])
+        );
+        assert_eq!(
+            keyed_order(&row_keys, &present(&["b", "a"])),
+            (vec![1, 0], vec![])
+        );
+    }
+
+    #[test]
+    fn keyed_order_appends_missing_rows() {
+        let row_keys = [key("c"), key("a"), key("d")];
+        assert_eq!(
+            keyed_order(&row_keys, &present(&["a"])),
+            (vec![1, 0, 2], vec![0, 2])
+        );
+    }
+
+    #[test]
+    fn keyed_order_skips_null_keys() {
+        let null_key = RowKey::from_value(&Option::<String>::None.to_value());
+        assert_eq!(null_key, None);
+        let row_keys = [null_key, key("a"), None, key("b")];
+        assert_eq!(
+            keyed_order(&row_keys, &present(&["a"])),
+            (vec![0, 1], vec![3])
+        );
+    }
+}
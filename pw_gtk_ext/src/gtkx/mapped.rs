@@ -16,9 +16,8 @@ pub trait MapManagedUpdate {
     fn set_required_map_action(&self, action: RequiredMapAction);
 
     fn auto_update(&self) {
-        match self.get_required_map_action() {
-            RequiredMapAction::Nothing => self.update(),
-            _ => (),
+        if self.get_required_map_action() == RequiredMapAction::Nothing {
+            self.update()
         }
     }
 
@@ -59,3 +58,101 @@ pub trait MapManagedUpdate {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    struct Updatable {
+        renews: Cell<u32>,
+        updates: Cell<u32>,
+        is_mapped: Cell<bool>,
+        required_map_action: Cell<RequiredMapAction>,
+    }
+
+    impl Updatable {
+        fn new(is_mapped: bool) -> Self {
+            Self {
+                renews: Cell::new(0),
+                updates: Cell::new(0),
+                is_mapped: Cell::new(is_mapped),
+                required_map_action: Cell::new(RequiredMapAction::Nothing),
+            }
+        }
+
+        fn counts(&self) -> (u32, u32) {
+            (self.renews.get(), self.updates.get())
+        }
+    }
+
+    impl MapManagedUpdate for Updatable {
+        fn do_renew(&self) {
+            self.renews.set(self.renews.get() + 1)
+        }
+
+        fn do_update(&self) {
+            self.updates.set(self.updates.get() + 1)
+        }
+
+        fn is_mapped(&self) -> bool {
+            self.is_mapped.get()
+        }
+
+        fn set_is_mapped(&self, value: bool) {
+            self.is_mapped.set(value)
+        }
+
+        fn get_required_map_action(&self) -> RequiredMapAction {
+            self.required_map_action.get()
+        }
+
+        fn set_required_map_action(&self, action: RequiredMapAction) {
+            self.required_map_action.set(action)
+        }
+    }
+
+    #[test]
+    fn mapped_updates_are_immediate() {
+        let updatable = Updatable::new(true);
+        updatable.update();
+        updatable.auto_update();
+        updatable.renew();
+        assert_eq!(updatable.counts(), (1, 2));
+        assert_eq!(
+            updatable.get_required_map_action(),
+            RequiredMapAction::Nothing
+        );
+    }
+
+    #[test]
+    fn unmapped_updates_are_deferred_until_mapped() {
+        let updatable = Updatable::new(false);
+        updatable.auto_update();
+        updatable.auto_update();
+        assert_eq!(updatable.counts(), (0, 0));
+        assert_eq!(
+            updatable.get_required_map_action(),
+            RequiredMapAction::Update
+        );
+        updatable.on_map_action();
+        assert_eq!(updatable.counts(), (0, 1));
+        updatable.on_unmap_action();
+        updatable.on_map_action();
+        assert_eq!(updatable.counts(), (0, 1));
+    }
+
+    #[test]
+    fn deferred_renewals_override_updates() {
+        let updatable = Updatable::new(false);
+        updatable.update();
+        updatable.renew();
+        updatable.update();
+        assert_eq!(
+            updatable.get_required_map_action(),
+            RequiredMapAction::Renew
+        );
+        updatable.on_map_action();
+        assert_eq!(updatable.counts(), (1, 0));
+    }
+}
@@ -1,172 +1,939 @@
 // Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
 
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::{Rc, Weak};
+
 use crate::gtk::prelude::*;
 
 use crate::{
     sav_state::{
         self, ChangedCondnsNotifier, ConditionalWidgets, ConditionalWidgetsBuilder, MaskedCondns,
-        WidgetStatesControlled,
+        Subscription, WidgetStateHandler, WidgetStatesControlled,
     },
     wrapper::*,
 };
 
+#[macro_use]
+pub mod definition;
+
+use definition::{CondnNames, DefnError, MenuDefn};
+
+/// The source of an item's icon.  Icons are resolved each time a widget
+/// is built so that the same spec can be used for several widgets.
+#[derive(Debug, Clone)]
+pub enum IconSource {
+    /// A themed icon name e.g. "edit-copy"
+    IconName(String),
+    GIcon(gio::Icon),
+    /// Image file data e.g. from `include_bytes!()`
+    PixbufBytes(&'static [u8]),
+    /// Use the first of the sources that can be resolved
+    WithFallback(Box<IconSource>, Box<IconSource>),
+}
+
+impl From<&str> for IconSource {
+    fn from(icon_name: &str) -> Self {
+        IconSource::IconName(icon_name.to_string())
+    }
+}
+
+impl IconSource {
+    pub fn with_fallback<S: Into<IconSource>>(self, fallback: S) -> Self {
+        IconSource::WithFallback(Box::new(self), Box::new(fallback.into()))
+    }
+
+    /// Create an image of the given size or `None` if the icon cannot
+    /// be resolved.
+    pub fn image(&self, size: gtk::IconSize) -> Option<gtk::Image> {
+        match self {
+            IconSource::IconName(icon_name) => {
+                let icon_theme = gtk::IconTheme::get_default()?;
+                if icon_theme.has_icon(icon_name) {
+                    Some(gtk::Image::from_icon_name(Some(icon_name), size))
+                } else {
+                    log::warn!("{}: icon not found in theme", icon_name);
+                    None
+                }
+            }
+            IconSource::GIcon(gicon) => Some(gtk::Image::from_gicon(gicon, size)),
+            IconSource::PixbufBytes(bytes) => {
+                let (width, height) = gtk::icon_size_lookup(size)?;
+                let bytes = glib::Bytes::from_static(bytes);
+                let stream = gio::MemoryInputStream::from_bytes(&bytes);
+                match gdk_pixbuf::Pixbuf::from_stream_at_scale(
+                    &stream,
+                    width,
+                    height,
+                    true,
+                    Option::<&gio::Cancellable>::None,
+                ) {
+                    Ok(pixbuf) => Some(gtk::Image::from_pixbuf(Some(&pixbuf))),
+                    Err(err) => {
+                        log::warn!("invalid icon data: {}", err);
+                        None
+                    }
+                }
+            }
+            IconSource::WithFallback(source, fallback) => {
+                source.image(size).or_else(|| fallback.image(size))
+            }
+        }
+    }
+}
+
+/// Label (with an optional mnemonic marked by an underscore), optional
+/// icon, optional tooltip and optional accelerator (e.g. "<Ctrl>C").
 #[derive(Debug, Clone)]
 pub struct MenuItemSpec(
-    pub &'static str,
-    pub Option<gtk::Image>,
-    pub Option<&'static str>,
+    pub String,
+    pub Option<IconSource>,
+    pub Option<String>,
+    pub Option<String>,
 );
 
-impl From<(&'static str, Option<gtk::Image>, Option<&'static str>)> for MenuItemSpec {
-    fn from(tuple_: (&'static str, Option<gtk::Image>, Option<&'static str>)) -> Self {
-        Self(tuple_.0, tuple_.1, tuple_.2)
+impl From<(&str, Option<IconSource>, Option<&str>)> for MenuItemSpec {
+    fn from(tuple_: (&str, Option<IconSource>, Option<&str>)) -> Self {
+        Self(
+            tuple_.0.to_string(),
+            tuple_.1,
+            tuple_.2.map(|s| s.to_string()),
+            None,
+        )
     }
 }
 
-impl From<&MenuItemSpec> for gtk::MenuItem {
-    fn from(menu_item_spec: &MenuItemSpec) -> Self {
+impl From<(&str, Option<IconSource>, Option<&str>, Option<&str>)> for MenuItemSpec {
+    fn from(tuple_: (&str, Option<IconSource>, Option<&str>, Option<&str>)) -> Self {
+        Self(
+            tuple_.0.to_string(),
+            tuple_.1,
+            tuple_.2.map(|s| s.to_string()),
+            tuple_.3.map(|s| s.to_string()),
+        )
+    }
+}
+
+impl MenuItemSpec {
+    pub fn accelerator(&self) -> Option<&str> {
+        self.3.as_deref()
+    }
+
+    /// The label with its mnemonic markers removed (and "__" replaced
+    /// by "_") e.g. for display where mnemonics aren't supported.
+    pub fn plain_label(&self) -> String {
+        let mut plain_label = String::with_capacity(self.0.len());
+        let mut chars = self.0.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '_' {
+                if let Some(next) = chars.next() {
+                    plain_label.push(next);
+                }
+            } else {
+                plain_label.push(ch);
+            }
+        }
+        plain_label
+    }
+
+    /// A new image (of the given size) for the spec's icon (if any).
+    pub fn image(&self, size: gtk::IconSize) -> Option<gtk::Image> {
+        self.1.as_ref()?.image(size)
+    }
+
+    fn decorate<I: IsA<gtk::MenuItem> + IsA<gtk::Widget>>(&self, item: &I) {
         let h_box = gtk::BoxBuilder::new()
             .orientation(gtk::Orientation::Horizontal)
             .spacing(6)
             .build();
-        if let Some(image) = &menu_item_spec.1 {
-            h_box.pack_start(image, false, false, 0);
+        if let Some(image) = self.image(gtk::IconSize::Menu) {
+            h_box.pack_start(&image, false, false, 0);
         }
-        let label = gtk::LabelBuilder::new()
-            .label(menu_item_spec.0)
+        let label = gtk::AccelLabelBuilder::new()
+            .label(&self.0)
+            .use_underline(true)
             .xalign(0.0)
             .build();
+        label.set_mnemonic_widget(Some(item));
+        label.set_accel_widget(Some(item));
         h_box.pack_start(&label, true, true, 0);
-        let item = gtk::MenuItemBuilder::new().child(&h_box).build();
-        item.set_tooltip_text(menu_item_spec.2);
+        item.add(&h_box);
+        item.set_tooltip_text(self.2.as_deref());
+    }
+}
+
+impl From<&MenuItemSpec> for gtk::MenuItem {
+    fn from(menu_item_spec: &MenuItemSpec) -> Self {
+        let item = gtk::MenuItem::new();
+        menu_item_spec.decorate(&item);
+
+        item
+    }
+}
+
+impl From<&MenuItemSpec> for gtk::CheckMenuItem {
+    fn from(menu_item_spec: &MenuItemSpec) -> Self {
+        let item = gtk::CheckMenuItem::new();
+        menu_item_spec.decorate(&item);
 
         item
     }
 }
 
+impl From<&MenuItemSpec> for gtk::RadioMenuItem {
+    fn from(menu_item_spec: &MenuItemSpec) -> Self {
+        let item = gtk::RadioMenuItemBuilder::new().build();
+        menu_item_spec.decorate(&item);
+
+        item
+    }
+}
+
+// Builders keep their own copies of the descriptions of their items.
+pub(crate) fn owned_items(items: &[(&str, MenuItemSpec, u64)]) -> Vec<(String, MenuItemSpec, u64)> {
+    items
+        .iter()
+        .map(|(name, menu_item_spec, condns)| (name.to_string(), menu_item_spec.clone(), *condns))
+        .collect()
+}
+
+/// A description of a managed menu item suitable for indexing.
+#[derive(Debug, Clone)]
+pub struct MenuCommand {
+    /// Submenu names and the item name separated by "/"
+    pub path: String,
+    /// The label with mnemonic markers removed
+    pub label: String,
+    pub tooltip: Option<String>,
+    pub accelerator: Option<String>,
+    /// Whether the item's (and any enclosing submenus') conditions are met
+    pub is_enabled: bool,
+    pub item: gtk::MenuItem,
+}
+
+impl MenuCommand {
+    pub fn activate(&self) {
+        self.item.activate();
+    }
+}
+
+pub type RadioGroupChangeCallback<T> = Box<dyn Fn(&T)>;
+
+pub struct MenuRadioGroupCore<T: Clone + PartialEq> {
+    items: Vec<(String, T, gtk::RadioMenuItem)>,
+    change_callbacks: RefCell<Vec<RadioGroupChangeCallback<T>>>,
+}
+
+/// A group of radio items in a `ManagedMenu` each associated with a
+/// value of type `T`.
+#[derive(WClone)]
+pub struct MenuRadioGroup<T: Clone + PartialEq>(Rc<MenuRadioGroupCore<T>>);
+
+impl<T: Clone + PartialEq + 'static> MenuRadioGroup<T> {
+    fn inform_change(&self, selected: &T) {
+        for callback in self.0.change_callbacks.borrow().iter() {
+            callback(selected)
+        }
+    }
+
+    // NB: groups are never empty and exactly one item is active
+    fn selected_item(&self) -> &(String, T, gtk::RadioMenuItem) {
+        self.0
+            .items
+            .iter()
+            .find(|(_, _, item)| item.get_active())
+            .unwrap_or(&self.0.items[0])
+    }
+
+    pub fn selected(&self) -> T {
+        self.selected_item().1.clone()
+    }
+
+    pub fn selected_name(&self) -> String {
+        self.selected_item().0.clone()
+    }
+
+    pub fn set_selected(&self, value: &T) -> Result<(), sav_state::Error> {
+        match self.0.items.iter().find(|(_, v, _)| v == value) {
+            Some((_, _, item)) => {
+                item.set_active(true);
+                Ok(())
+            }
+            None => Err(sav_state::Error::NotFound),
+        }
+    }
+
+    pub fn set_selected_name(&self, name: &str) -> Result<(), sav_state::Error> {
+        match self.0.items.iter().find(|(n, _, _)| *n == name) {
+            Some((_, _, item)) => {
+                item.set_active(true);
+                Ok(())
+            }
+            None => Err(sav_state::Error::NotFound),
+        }
+    }
+
+    pub fn connect_changed<F: Fn(&T) + 'static>(&self, callback: F) {
+        let boxed = Box::new(callback);
+        self.0.change_callbacks.borrow_mut().push(boxed);
+    }
+
+    /// Restore the selection remembered under `key` (if any) and
+    /// remember the name of the selected item whenever it changes.
+    pub fn remember_as(&self, key: &str) {
+        if let Some(name) = recollections::recall(key) {
+            if self.set_selected_name(&name).is_err() {
+                log::warn!("{}: unknown radio item: {}", key, name);
+            }
+        }
+        let weak_core = Rc::downgrade(&self.0);
+        let key = key.to_string();
+        self.connect_changed(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                recollections::remember(&key, &MenuRadioGroup(core).selected_name())
+            }
+        });
+    }
+}
+
 #[derive(PWO)]
-pub struct ManagedMenu {
+pub struct ManagedMenuCore {
     menu: gtk::Menu,
-    items: ConditionalWidgets<&'static str, gtk::MenuItem>,
+    items: ConditionalWidgets<String, gtk::MenuItem>,
+    accel_group: gtk::AccelGroup,
+    submenus: RefCell<Vec<(String, ManagedMenu)>>,
+    // The menu containing the item that this menu is the submenu of
+    // and that item's name.
+    parent: RefCell<Option<(Weak<ManagedMenuCore>, String)>>,
+    sections: RefCell<Vec<(String, gtk::SeparatorMenuItem, Vec<String>)>>,
+    specs: RefCell<HashMap<String, MenuItemSpec>>,
+    subscription: RefCell<Option<Subscription>>,
 }
 
+#[derive(PWO, WClone)]
+pub struct ManagedMenu(Rc<ManagedMenuCore>);
+
 impl ManagedMenu {
+    fn new(items: ConditionalWidgets<String, gtk::MenuItem>, accel_group: gtk::AccelGroup) -> Self {
+        let menu = gtk::MenuBuilder::new().build();
+        menu.set_accel_group(Some(&accel_group));
+        let mm = Self(Rc::new(ManagedMenuCore {
+            menu,
+            items,
+            accel_group,
+            submenus: RefCell::new(vec![]),
+            parent: RefCell::new(None),
+            sections: RefCell::new(vec![]),
+            specs: RefCell::new(HashMap::new()),
+            subscription: RefCell::new(None),
+        }));
+        // NB: these are called after those of `items` (which were
+        // connected first) so the items' states are already up to date.
+        // Each submenu is updated by its own `items` and then refreshes
+        // its item in the parent menu.
+        let subscription =
+            mm.0.items
+                .change_notifier()
+                .subscribe_weak(&mm.0, |core, _| {
+                    ManagedMenu(Rc::clone(core)).refresh_parent_item()
+                });
+        *mm.0.subscription.borrow_mut() = Some(subscription);
+        if let Some(selection) = mm.0.items.selection() {
+            let weak_core = Rc::downgrade(&mm.0);
+            selection.connect_changed(move |_| {
+                if let Some(core) = weak_core.upgrade() {
+                    ManagedMenu(core).refresh_parent_item()
+                }
+            });
+        }
+        mm
+    }
+
     pub fn menu(&self) -> gtk::Menu {
-        self.menu.clone()
+        self.0.menu.clone()
+    }
+
+    /// The accelerator group that item accelerators are registered
+    /// with.  Add it to the top level window with `add_accel_group()`.
+    pub fn accel_group(&self) -> &gtk::AccelGroup {
+        &self.0.accel_group
+    }
+
+    pub fn change_notifier(&self) -> &ChangedCondnsNotifier {
+        self.0.items.change_notifier()
+    }
+
+    // Keep the spec for use by command palettes etc.
+    fn register_spec(&self, name: &str, item: &gtk::MenuItem, menu_item_spec: &MenuItemSpec) {
+        self.0
+            .specs
+            .borrow_mut()
+            .insert(name.to_string(), menu_item_spec.clone());
+        if let Some(accel) = menu_item_spec.accelerator() {
+            self.0.items.add_accelerator(
+                name.to_string(),
+                item,
+                "activate",
+                &self.0.accel_group,
+                accel,
+            );
+        }
+    }
+
+    pub fn menu_item(&self, name: &str) -> Result<gtk::MenuItem, sav_state::Error> {
+        self.0.items.get_widget(name)
     }
 
-    pub fn menu_item(&self, name: &'static str) -> Result<gtk::MenuItem, sav_state::Error> {
-        self.items.get_widget(&name)
+    /// Find the item at `path` where submenu names are separated by
+    /// "/" e.g. "edit/paste special".
+    pub fn menu_item_at_path(&self, path: &str) -> Result<gtk::MenuItem, sav_state::Error> {
+        match path.rsplit_once('/') {
+            Some((submenu_path, name)) => {
+                self.submenu_at_path(submenu_path)?.0.items.get_widget(name)
+            }
+            None => self.0.items.get_widget(path),
+        }
+    }
+
+    pub fn submenu(&self, name: &str) -> Result<ManagedMenu, sav_state::Error> {
+        self.0
+            .submenus
+            .borrow()
+            .iter()
+            .find(|(sm_name, _)| *sm_name == name)
+            .map(|(_, submenu)| submenu.clone())
+            .ok_or(sav_state::Error::NotFound)
+    }
+
+    /// Find the submenu at `path` where submenu names are separated by
+    /// "/" e.g. "edit/paste special".
+    pub fn submenu_at_path(&self, path: &str) -> Result<ManagedMenu, sav_state::Error> {
+        let mut submenu = self.clone();
+        for name in path.split('/') {
+            submenu = submenu.submenu(name)?;
+        }
+        Ok(submenu)
     }
 
     pub fn append_menu_item(
         &self,
-        name: &'static str,
+        name: &str,
         item: &gtk::MenuItem,
         condns: u64,
     ) -> Result<(), sav_state::Error> {
-        self.items.add_widget(name, item, condns)?;
-        self.menu.append(item);
-        self.menu.show_all();
+        // NB: show the item before adding it so that the state
+        // implied by its conditions (which may hide it) prevails.
+        item.show_all();
+        self.0.items.add_widget(name.to_string(), item, condns)?;
+        self.0.menu.append(item);
+        self.refresh_parent_item();
         Ok(())
     }
 
     pub fn insert_menu_item(
         &self,
-        name: &'static str,
+        name: &str,
         item: &gtk::MenuItem,
         condns: u64,
         position: i32,
     ) -> Result<(), sav_state::Error> {
-        self.items.add_widget(name, item, condns)?;
-        self.menu.insert(item, position);
-        self.menu.show_all();
+        // NB: show the item before adding it so that the state
+        // implied by its conditions (which may hide it) prevails.
+        item.show_all();
+        self.0.items.add_widget(name.to_string(), item, condns)?;
+        self.0.menu.insert(item, position);
+        self.refresh_parent_item();
         Ok(())
     }
 
     pub fn prepend_menu_item(
         &self,
-        name: &'static str,
+        name: &str,
         item: &gtk::MenuItem,
         condns: u64,
     ) -> Result<(), sav_state::Error> {
-        self.items.add_widget(name, item, condns)?;
-        self.menu.prepend(item);
-        self.menu.show_all();
+        // NB: show the item before adding it so that the state
+        // implied by its conditions (which may hide it) prevails.
+        item.show_all();
+        self.0.items.add_widget(name.to_string(), item, condns)?;
+        self.0.menu.prepend(item);
+        self.refresh_parent_item();
         Ok(())
     }
 
     pub fn append_item(
         &self,
-        name: &'static str,
+        name: &str,
         menu_item_spec: &MenuItemSpec,
         condns: u64,
     ) -> Result<gtk::MenuItem, sav_state::Error> {
         let item = menu_item_spec.into();
         self.append_menu_item(name, &item, condns)?;
+        self.register_spec(name, &item, menu_item_spec);
 
         Ok(item)
     }
 
     pub fn insert_item(
         &self,
-        name: &'static str,
+        name: &str,
         menu_item_spec: &MenuItemSpec,
         condns: u64,
         position: i32,
     ) -> Result<gtk::MenuItem, sav_state::Error> {
         let item = menu_item_spec.into();
         self.insert_menu_item(name, &item, condns, position)?;
+        self.register_spec(name, &item, menu_item_spec);
 
         Ok(item)
     }
 
     pub fn prepend_item(
         &self,
-        name: &'static str,
+        name: &str,
         menu_item_spec: &MenuItemSpec,
         condns: u64,
     ) -> Result<gtk::MenuItem, sav_state::Error> {
         let item = menu_item_spec.into();
         self.prepend_menu_item(name, &item, condns)?;
+        self.register_spec(name, &item, menu_item_spec);
 
         Ok(item)
     }
 
+    /// Append a check item whose initial state is `active`.
+    pub fn append_check_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+        active: bool,
+    ) -> Result<gtk::CheckMenuItem, sav_state::Error> {
+        let item: gtk::CheckMenuItem = menu_item_spec.into();
+        item.set_active(active);
+        self.append_menu_item(name, item.upcast_ref::<gtk::MenuItem>(), condns)?;
+        self.register_spec(name, item.upcast_ref::<gtk::MenuItem>(), menu_item_spec);
+
+        Ok(item)
+    }
+
+    pub fn check_item(&self, name: &str) -> Result<gtk::CheckMenuItem, sav_state::Error> {
+        match self
+            .0
+            .items
+            .get_widget(name)?
+            .downcast::<gtk::CheckMenuItem>()
+        {
+            Ok(item) => Ok(item),
+            Err(_) => Err(sav_state::Error::NotFound),
+        }
+    }
+
+    pub fn is_check_item_active(&self, name: &str) -> Result<bool, sav_state::Error> {
+        Ok(self.check_item(name)?.get_active())
+    }
+
+    pub fn set_check_item_active(&self, name: &str, active: bool) -> Result<(), sav_state::Error> {
+        self.check_item(name)?.set_active(active);
+        Ok(())
+    }
+
+    /// Restore the state of the check item `name` remembered under
+    /// `key` (if any) and remember its state whenever it is toggled.
+    pub fn remember_check_item(&self, name: &str, key: &str) -> Result<(), sav_state::Error> {
+        let item = self.check_item(name)?;
+        if let Some(text) = recollections::recall(key) {
+            match text.parse::<bool>() {
+                Ok(active) => item.set_active(active),
+                Err(err) => log::error!("Error parsing \"{}\": {}", key, err),
+            }
+        }
+        let key = key.to_string();
+        item.connect_toggled(move |item| {
+            recollections::remember(&key, &item.get_active().to_string())
+        });
+        Ok(())
+    }
+
+    /// Append a group of radio items each associated with a value.  The
+    /// first item is initially selected.  `items` must not be empty.
+    /// If any item can't be appended none of them are.
+    /// NB: the returned group must be kept for as long as its change
+    /// callbacks (including `remember_as()`'s) are required.
+    pub fn append_radio_group<T: Clone + PartialEq + 'static>(
+        &self,
+        items: &[(&str, MenuItemSpec, T, u64)],
+    ) -> Result<MenuRadioGroup<T>, sav_state::Error> {
+        if items.is_empty() {
+            return Err(sav_state::Error::EmptyGroup);
+        }
+        let mut radio_items = vec![];
+        let mut group: Option<gtk::RadioMenuItem> = None;
+        for (name, menu_item_spec, value, condns) in items.iter() {
+            let item: gtk::RadioMenuItem = menu_item_spec.into();
+            if let Some(ref group) = group {
+                item.join_group(Some(group));
+            } else {
+                group = Some(item.clone());
+            }
+            if let Err(err) =
+                self.append_menu_item(name, item.upcast_ref::<gtk::MenuItem>(), *condns)
+            {
+                for (name, _, _) in radio_items.iter() {
+                    if let Err(err) = self.remove_item(name) {
+                        log::error!("{}: {}", name, err);
+                    }
+                }
+                return Err(err);
+            }
+            self.register_spec(name, item.upcast_ref::<gtk::MenuItem>(), menu_item_spec);
+            radio_items.push((name.to_string(), value.clone(), item));
+        }
+        let radio_group = MenuRadioGroup(Rc::new(MenuRadioGroupCore {
+            items: radio_items,
+            change_callbacks: RefCell::new(vec![]),
+        }));
+        for (_, value, item) in radio_group.0.items.iter() {
+            let weak_core = Rc::downgrade(&radio_group.0);
+            let value_c = value.clone();
+            item.connect_toggled(move |item| {
+                if item.get_active() {
+                    if let Some(core) = weak_core.upgrade() {
+                        MenuRadioGroup(core).inform_change(&value_c);
+                    }
+                }
+            });
+        }
+
+        Ok(radio_group)
+    }
+
+    /// Append an item described by `menu_item_spec` with a submenu
+    /// that shares this menu's conditions.  The item is only switched
+    /// on when `condns` are satisfied and at least one of the
+    /// submenu's items is switched on.
+    pub fn append_submenu(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+    ) -> Result<ManagedMenu, sav_state::Error> {
+        let mut builder = ConditionalWidgetsBuilder::new();
+        builder
+            .widget_states_controlled(self.0.items.widget_states_controlled())
+            .change_notifier(self.0.items.change_notifier());
+        if let Some(selection) = self.0.items.selection() {
+            builder.selection(selection);
+        }
+        let submenu = ManagedMenu::new(
+            builder.build::<String, gtk::MenuItem>(),
+            self.0.accel_group.clone(),
+        );
+
+        *submenu.0.parent.borrow_mut() = Some((Rc::downgrade(&self.0), name.to_string()));
+
+        let item: gtk::MenuItem = menu_item_spec.into();
+        item.set_submenu(Some(&submenu.0.menu));
+        let wsc = self.0.items.widget_states_controlled();
+        let weak_core = Rc::downgrade(&submenu.0);
+        let handler = WidgetStateHandler::new(move |item: &gtk::MenuItem, on| {
+            let any_on = match weak_core.upgrade() {
+                Some(core) => ManagedMenu(core).has_enabled_items(),
+                None => false,
+            };
+            wsc.apply(item, on && any_on)
+        });
+        item.show_all();
+        self.0
+            .items
+            .add_widget_with_handler(name.to_string(), &item, condns, handler)?;
+        self.0.menu.append(&item);
+        self.0
+            .submenus
+            .borrow_mut()
+            .push((name.to_string(), submenu.clone()));
+        self.refresh_parent_item();
+
+        Ok(submenu)
+    }
+
+    /// Descriptions of all of the (non submenu) items in this menu and
+    /// its submenus that were created from a `MenuItemSpec`.
+    pub fn commands(&self) -> Vec<MenuCommand> {
+        let mut commands = vec![];
+        self.collect_commands("", true, &mut commands);
+        commands.sort_by(|a, b| a.path.cmp(&b.path));
+        commands
+    }
+
+    fn collect_commands(&self, prefix: &str, parent_on: bool, commands: &mut Vec<MenuCommand>) {
+        let submenus = self.0.submenus.borrow();
+        for (name, menu_item_spec) in self.0.specs.borrow().iter() {
+            if let Ok(item) = self.0.items.get_widget(name) {
+                commands.push(MenuCommand {
+                    path: format!("{}{}", prefix, name),
+                    label: menu_item_spec.plain_label(),
+                    tooltip: menu_item_spec.2.clone(),
+                    accelerator: menu_item_spec.3.clone(),
+                    is_enabled: parent_on && self.0.items.is_on(name) == Some(true),
+                    item,
+                });
+            }
+        }
+        for (name, submenu) in submenus.iter() {
+            let on = parent_on && self.0.items.is_on(name) == Some(true);
+            submenu.collect_commands(&format!("{}{}/", prefix, name), on, commands);
+        }
+    }
+
+    /// Whether any of this menu's items (taking submenus into account)
+    /// are currently switched on.
+    pub fn has_enabled_items(&self) -> bool {
+        let submenus = self.0.submenus.borrow();
+        self.0.items.keys().iter().any(|name| {
+            if self.0.items.is_on(name) != Some(true) {
+                false
+            } else if let Some((_, submenu)) = submenus.iter().find(|(sm_name, _)| sm_name == name)
+            {
+                submenu.has_enabled_items()
+            } else {
+                true
+            }
+        })
+    }
+
+    // Bring the state of the item that this menu is the submenu of
+    // into line with this menu's contents (and so on up the chain).
+    fn refresh_parent_item(&self) {
+        let o_parent = self
+            .0
+            .parent
+            .borrow()
+            .as_ref()
+            .and_then(|(weak_core, name)| Some((weak_core.upgrade()?, name.clone())));
+        if let Some((core, name)) = o_parent {
+            let parent = ManagedMenu(core);
+            if let Ok(item) = parent.0.items.get_widget(&name) {
+                let on = parent.0.items.is_on(&name) == Some(true) && self.has_enabled_items();
+                parent.0.items.widget_states_controlled().apply(&item, on);
+            }
+            parent.refresh_parent_item();
+        }
+    }
+
+    /// Remove the item called `name` (along with its submenu if it has
+    /// one) from the menu.
+    pub fn remove_item(&self, name: &str) -> Result<gtk::MenuItem, sav_state::Error> {
+        let item = self.0.items.remove_widget(name)?;
+        self.0.specs.borrow_mut().remove(name);
+        self.0.submenus.borrow_mut().retain(|(sm_name, submenu)| {
+            if sm_name == name {
+                *submenu.0.parent.borrow_mut() = None;
+                false
+            } else {
+                true
+            }
+        });
+        for (_, _, names) in self.0.sections.borrow_mut().iter_mut() {
+            names.retain(|sn_name| sn_name != name);
+        }
+        self.0.menu.remove(&item);
+        self.refresh_parent_item();
+        Ok(item)
+    }
+
+    /// Append an (initially empty) section called `section` whose items
+    /// can be regenerated from data with `replace_section()`.  The
+    /// section is preceded by a separator that is only shown while the
+    /// section is not empty.
+    pub fn append_dynamic_section(&self, section: &str) -> Result<(), sav_state::Error> {
+        let mut sections = self.0.sections.borrow_mut();
+        if sections.iter().any(|(sn_name, _, _)| sn_name == section) {
+            return Err(sav_state::Error::DuplicateKey);
+        }
+        let separator = gtk::SeparatorMenuItem::new();
+        separator.set_no_show_all(true);
+        self.0.menu.append(&separator);
+        sections.push((section.to_string(), separator, vec![]));
+        Ok(())
+    }
+
+    fn section_separator(&self, section: &str) -> Result<gtk::SeparatorMenuItem, sav_state::Error> {
+        self.0
+            .sections
+            .borrow()
+            .iter()
+            .find(|(sn_name, _, _)| sn_name == section)
+            .map(|(_, separator, _)| separator.clone())
+            .ok_or(sav_state::Error::NotFound)
+    }
+
+    pub fn clear_dynamic_section(&self, section: &str) -> Result<(), sav_state::Error> {
+        let separator = self.section_separator(section)?;
+        let names = match self
+            .0
+            .sections
+            .borrow_mut()
+            .iter_mut()
+            .find(|(sn_name, _, _)| sn_name == section)
+        {
+            Some((_, _, names)) => std::mem::take(names),
+            None => vec![],
+        };
+        // NB: carry on regardless so that the section is always emptied
+        let mut result = Ok(());
+        for name in names.iter() {
+            if let Err(err) = self.remove_item(name) {
+                log::error!("{}: {}: {}", section, name, err);
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        separator.hide();
+        result
+    }
+
+    /// Replace the contents of the dynamic section `section` with `items`.
+    pub fn replace_section(
+        &self,
+        section: &str,
+        items: &[(String, MenuItemSpec, u64)],
+    ) -> Result<(), sav_state::Error> {
+        self.clear_dynamic_section(section)?;
+        let separator = self.section_separator(section)?;
+        let position = self
+            .0
+            .menu
+            .get_children()
+            .iter()
+            .position(|child| child == separator.upcast_ref::<gtk::Widget>())
+            .ok_or(sav_state::Error::NotFound)? as i32;
+        for (index, (name, menu_item_spec, condns)) in items.iter().enumerate() {
+            self.insert_item(name, menu_item_spec, *condns, position + 1 + index as i32)?;
+            if let Some((_, _, names)) = self
+                .0
+                .sections
+                .borrow_mut()
+                .iter_mut()
+                .find(|(sn_name, _, _)| sn_name == section)
+            {
+                names.push(name.to_string());
+            }
+        }
+        separator.set_visible(!items.is_empty());
+        Ok(())
+    }
+
     pub fn append_separator(&self) {
-        self.menu.append(&gtk::SeparatorMenuItem::new());
-        self.menu.show_all();
+        let separator = gtk::SeparatorMenuItem::new();
+        separator.show();
+        self.0.menu.append(&separator);
     }
 
     pub fn insert_separator(&self, position: i32) {
-        self.menu.insert(&gtk::SeparatorMenuItem::new(), position);
-        self.menu.show_all();
+        let separator = gtk::SeparatorMenuItem::new();
+        separator.show();
+        self.0.menu.insert(&separator, position);
     }
 
     pub fn prepend_separator(&self) {
-        self.menu.prepend(&gtk::SeparatorMenuItem::new());
-        self.menu.show_all();
+        let separator = gtk::SeparatorMenuItem::new();
+        separator.show();
+        self.0.menu.prepend(&separator);
     }
 
+    // NB: changes made via these (rather than the change notifier)
+    // aren't seen by the submenus' items so they are passed on.
     pub fn update_condns(&self, changed_condns: MaskedCondns) {
-        self.items.update_condns(changed_condns)
+        self.0.items.update_condns(changed_condns);
+        for submenu in self.submenus() {
+            submenu.update_condns(changed_condns);
+        }
+        self.refresh_parent_item();
     }
 
     pub fn update_hover_condns(&self, hover_ok: bool) {
-        self.items.update_hover_condns(hover_ok)
+        self.0.items.update_hover_condns(hover_ok);
+        for submenu in self.submenus() {
+            submenu.update_hover_condns(hover_ok);
+        }
+        self.refresh_parent_item();
+    }
+
+    fn submenus(&self) -> Vec<ManagedMenu> {
+        self.0
+            .submenus
+            .borrow()
+            .iter()
+            .map(|(_, submenu)| submenu.clone())
+            .collect()
     }
 
     pub fn popup_at_event(&self, event: &gdk::EventButton) {
-        if !self.items.is_empty() {
-            self.menu.popup_easy(event.get_button(), event.get_time());
+        if !self.0.items.is_empty() {
+            self.0.menu.popup_at_pointer(Some(&**event));
+        }
+    }
+
+    /// Pop up the menu at the pointer's current position e.g. in
+    /// response to an event that isn't a button press.
+    pub fn popup_at_pointer(&self) {
+        if !self.0.items.is_empty() {
+            let event = gtk::get_current_event();
+            self.0.menu.popup_at_pointer(event.as_ref());
+        }
+    }
+
+    /// Pop up the menu with its top left corner at the bottom left of
+    /// `widget`.
+    pub fn popup_at_widget<W: IsA<gtk::Widget>>(&self, widget: &W) {
+        if !self.0.items.is_empty() {
+            let event = gtk::get_current_event();
+            self.0.menu.popup_at_widget(
+                widget,
+                gdk::Gravity::SouthWest,
+                gdk::Gravity::NorthWest,
+                event.as_ref(),
+            );
+        }
+    }
+
+    /// Pop up the menu with its top left corner at the bottom left of
+    /// `rect` (in `window`'s coordinates).
+    pub fn popup_at_rect(&self, window: &gdk::Window, rect: &gdk::Rectangle) {
+        if !self.0.items.is_empty() {
+            let event = gtk::get_current_event();
+            self.0.menu.popup_at_rect(
+                window,
+                rect,
+                gdk::Gravity::SouthWest,
+                gdk::Gravity::NorthWest,
+                event.as_ref(),
+            );
         }
     }
+
+    /// Pop up the menu at `widget` when the user presses the Menu key
+    /// or Shift+F10 while it has the focus.
+    pub fn connect_keyboard_popup<W: IsA<gtk::Widget>>(&self, widget: &W) {
+        let weak_core = Rc::downgrade(&self.0);
+        widget.connect_popup_menu(move |widget| match weak_core.upgrade() {
+            Some(core) => {
+                ManagedMenu(core).popup_at_widget(widget);
+                true
+            }
+            None => false,
+        });
+    }
 }
 
 #[derive(Default)]
 pub struct ManagedMenuBuilder {
     conditional_widgets_builder: ConditionalWidgetsBuilder,
-    items: Vec<(&'static str, MenuItemSpec, u64)>,
+    accel_group: Option<gtk::AccelGroup>,
+    items: Vec<(String, MenuItemSpec, u64)>,
+    definition: Option<(MenuDefn, CondnNames)>,
 }
 
 impl ManagedMenuBuilder {
@@ -194,24 +961,195 @@ impl ManagedMenuBuilder {
         self
     }
 
-    pub fn items(&mut self, items: &[(&'static str, MenuItemSpec, u64)]) -> &Self {
-        self.items = items.to_vec();
+    /// Register item accelerators with `accel_group` (e.g. one already
+    /// added to the top level window) instead of a new one.
+    pub fn accel_group(&mut self, accel_group: &gtk::AccelGroup) -> &mut Self {
+        self.accel_group = Some(accel_group.clone());
         self
     }
 
-    pub fn build(&self) -> ManagedMenu {
-        let menu = gtk::MenuBuilder::new().build();
+    pub fn items(&mut self, items: &[(&str, MenuItemSpec, u64)]) -> &Self {
+        self.items = owned_items(items);
+        self
+    }
+
+    /// Add the entries described by `menu_defn` (after any `items`)
+    /// evaluating their condition expressions using `condn_names`.
+    pub fn definition(&mut self, menu_defn: MenuDefn, condn_names: CondnNames) -> &mut Self {
+        self.definition = Some((menu_defn, condn_names));
+        self
+    }
+
+    /// Build the menu reporting any errors in its definition (including
+    /// names that clash with those of `items`).  Nothing is built if
+    /// there are errors.
+    pub fn try_build(&self) -> Result<ManagedMenu, Vec<DefnError>> {
+        let mut errors = vec![];
+        let mut names = HashSet::new();
+        for (name, _, _) in self.items.iter() {
+            if !names.insert(name.as_str()) {
+                errors.push(DefnError::DuplicateName(name.to_string()));
+            }
+        }
+        if let Some((menu_defn, condn_names)) = &self.definition {
+            if let Err(mut defn_errors) = menu_defn.validate_after(&names, condn_names) {
+                errors.append(&mut defn_errors);
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
         let items = self
             .conditional_widgets_builder
-            .build::<&'static str, gtk::MenuItem>();
-        let mm = ManagedMenu { menu, items };
+            .build::<String, gtk::MenuItem>();
+        let accel_group = match &self.accel_group {
+            Some(accel_group) => accel_group.clone(),
+            None => gtk::AccelGroup::new(),
+        };
+        let mm = ManagedMenu::new(items, accel_group);
         for (name, menu_item_spec, condns) in self.items.iter() {
             if let Err(err) = mm.append_item(name, menu_item_spec, *condns) {
-                panic!("Error adding item '{}' to menu: {}", name, err);
+                return Err(vec![DefnError::Menu(name.to_string(), err)]);
             };
         }
-        mm.menu.show_all();
+        if let Some((menu_defn, condn_names)) = &self.definition {
+            mm.append_definition(menu_defn, condn_names)?;
+        }
 
-        mm
+        Ok(mm)
+    }
+
+    pub fn build(&self) -> ManagedMenu {
+        match self.try_build() {
+            Ok(mm) => mm,
+            Err(errors) => {
+                let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                panic!("Error building menu: {}", errors.join("; "));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sav_state::SAV_NEXT_CONDN;
+
+    const CONDN: u64 = SAV_NEXT_CONDN;
+
+    #[test]
+    fn plain_labels_keep_escaped_underscores() {
+        let plain_label = |label: &str| MenuItemSpec::from((label, None, None)).plain_label();
+        assert_eq!(plain_label("_Copy"), "Copy");
+        assert_eq!(plain_label("Save _As"), "Save As");
+        assert_eq!(plain_label("snake__case _name"), "snake_case name");
+        assert_eq!(plain_label("___x"), "_x");
+        assert_eq!(plain_label("plain"), "plain");
+    }
+
+    #[test]
+    fn submenu_item_follows_submenu_contents() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let notifier = ChangedCondnsNotifier::new(0);
+        let menu = ManagedMenuBuilder::new().change_notifier(&notifier).build();
+        let submenu = menu
+            .append_submenu("edit", &MenuItemSpec::from(("_Edit", None, None)), 0)
+            .unwrap();
+        let item = menu.menu_item("edit").unwrap();
+        assert!(!item.get_sensitive());
+
+        submenu
+            .append_item("copy", &MenuItemSpec::from(("_Copy", None, None)), CONDN)
+            .unwrap();
+        assert!(!item.get_sensitive());
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: CONDN,
+            mask: CONDN,
+        });
+        assert!(item.get_sensitive());
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: 0,
+            mask: CONDN,
+        });
+        assert!(!item.get_sensitive());
+
+        submenu
+            .append_item("paste", &MenuItemSpec::from(("_Paste", None, None)), 0)
+            .unwrap();
+        assert!(item.get_sensitive());
+        submenu.remove_item("paste").unwrap();
+        assert!(!item.get_sensitive());
+    }
+
+    #[test]
+    fn hidden_items_stay_hidden_when_items_are_added() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let notifier = ChangedCondnsNotifier::new(0);
+        let menu = ManagedMenuBuilder::new()
+            .widget_states_controlled(WidgetStatesControlled::Visibility)
+            .change_notifier(&notifier)
+            .items(&[("copy", MenuItemSpec::from(("_Copy", None, None)), CONDN)])
+            .build();
+        let copy = menu.menu_item("copy").unwrap();
+        assert!(!copy.get_visible());
+
+        let paste = menu
+            .append_item("paste", &MenuItemSpec::from(("_Paste", None, None)), 0)
+            .unwrap();
+        menu.append_separator();
+        menu.append_submenu("edit", &MenuItemSpec::from(("_Edit", None, None)), 0)
+            .unwrap();
+        assert!(paste.get_visible());
+        assert!(!copy.get_visible());
+        assert!(!menu.menu_item("edit").unwrap().get_visible());
+
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: CONDN,
+            mask: CONDN,
+        });
+        assert!(copy.get_visible());
+    }
+
+    #[test]
+    fn clashing_names_are_reported_before_building() {
+        let mut builder = ManagedMenuBuilder::new();
+        builder.items(&[
+            ("copy", MenuItemSpec::from(("_Copy", None, None)), 0),
+            ("paste", MenuItemSpec::from(("_Paste", None, None)), 0),
+        ]);
+        builder.definition(
+            crate::menu! {
+                item "cut": "Cu_t";
+                item "copy": "Copy _Again";
+            },
+            CondnNames::standard(),
+        );
+        let errors = builder.try_build().err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(&errors[0], DefnError::DuplicateName(path) if path == "copy"));
+    }
+
+    #[test]
+    fn failed_radio_group_leaves_no_items() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let menu = ManagedMenuBuilder::new().build();
+        menu.append_item("copy", &MenuItemSpec::from(("_Copy", None, None)), 0)
+            .unwrap();
+        let result = menu.append_radio_group(&[
+            ("one", MenuItemSpec::from(("_One", None, None)), 1, 0),
+            ("copy", MenuItemSpec::from(("_Two", None, None)), 2, 0),
+        ]);
+        assert!(matches!(result, Err(sav_state::Error::DuplicateKey)));
+        assert!(menu.menu_item("one").is_err());
+        assert_eq!(menu.menu().get_children().len(), 1);
     }
 }
@@ -0,0 +1,458 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Declarative descriptions of the contents of a `ManagedMenu`.  Items
+//! refer to icons by icon name and to their required conditions by
+//! name so that a description can be written (e.g. with the `menu!`
+//! macro) without long tuples of `(&str, MenuItemSpec, u64)`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::sav_state::{
+    self, SAV_DONT_CARE, SAV_HOVER_NOT_OK, SAV_HOVER_OK, SAV_SELN_MADE, SAV_SELN_MADE_OR_HOVER_OK,
+    SAV_SELN_NONE, SAV_SELN_NONE_BUT_HOVER_OK, SAV_SELN_PAIR, SAV_SELN_UNIQUE,
+    SAV_SELN_UNIQUE_OR_HOVER_OK,
+};
+
+use super::{IconSource, ManagedMenu, MenuItemSpec};
+
+#[derive(Debug)]
+pub enum DefnError {
+    UnknownCondn(String, String),
+    DuplicateName(String),
+    EmptyName(String),
+    UnknownKind(String, String),
+    InvalidAccelerator(String, String),
+    Menu(String, sav_state::Error),
+}
+
+impl std::fmt::Display for DefnError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use DefnError::*;
+        match self {
+            UnknownCondn(path, condn) => write!(f, "{}: unknown condition: \"{}\"", path, condn),
+            DuplicateName(path) => write!(f, "{}: duplicate name", path),
+            EmptyName(path) => write!(f, "{}: empty name", path),
+            UnknownKind(path, kind) => write!(f, "{}: unknown entry kind: \"{}\"", path, kind),
+            InvalidAccelerator(path, accel) => {
+                write!(f, "{}: invalid accelerator: \"{}\"", path, accel)
+            }
+            Menu(path, err) => write!(f, "{}: {}", path, err),
+        }
+    }
+}
+
+impl std::error::Error for DefnError {}
+
+/// A mapping from condition names to conditions used to evaluate the
+/// condition expressions in a menu definition.
+#[derive(Debug, Clone, Default)]
+pub struct CondnNames(HashMap<String, u64>);
+
+impl CondnNames {
+    /// Names for the standard selection and hover conditions with
+    /// their "SAV_" prefix removed e.g. "SELN_MADE" and "HOVER_OK".
+    pub fn standard() -> Self {
+        let mut names = Self::default();
+        names
+            .add("DONT_CARE", SAV_DONT_CARE)
+            .add("SELN_NONE", SAV_SELN_NONE)
+            .add("SELN_MADE", SAV_SELN_MADE)
+            .add("SELN_UNIQUE", SAV_SELN_UNIQUE)
+            .add("SELN_PAIR", SAV_SELN_PAIR)
+            .add("SELN_MADE_OR_HOVER_OK", SAV_SELN_MADE_OR_HOVER_OK)
+            .add("SELN_UNIQUE_OR_HOVER_OK", SAV_SELN_UNIQUE_OR_HOVER_OK)
+            .add("SELN_NONE_BUT_HOVER_OK", SAV_SELN_NONE_BUT_HOVER_OK)
+            .add("HOVER_OK", SAV_HOVER_OK)
+            .add("HOVER_NOT_OK", SAV_HOVER_NOT_OK);
+        names
+    }
+
+    pub fn add(&mut self, name: &str, condns: u64) -> &mut Self {
+        self.0.insert(name.to_string(), condns);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<u64> {
+        self.0.get(name).copied()
+    }
+
+    /// Evaluate `expr` which is a list of condition names separated by
+    /// "|" (all of which are required).  An empty expression requires
+    /// no conditions.
+    pub fn evaluate(&self, expr: &str) -> Result<u64, String> {
+        let mut condns = 0;
+        for name in expr.split('|').map(|name| name.trim()) {
+            if name.is_empty() {
+                continue;
+            }
+            match self.get(name) {
+                Some(condn) => condns |= condn,
+                None => return Err(name.to_string()),
+            }
+        }
+        Ok(condns)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum EntryKind {
+    Item,
+    Check(bool),
+    Submenu(Vec<MenuEntryDefn>),
+    Separator,
+}
+
+#[derive(Debug, Clone)]
+pub struct MenuEntryDefn {
+    name: String,
+    label: String,
+    icon_name: Option<String>,
+    tooltip: Option<String>,
+    accel: Option<String>,
+    condns: String,
+    kind: EntryKind,
+}
+
+impl MenuEntryDefn {
+    fn new(name: &str, label: &str, kind: EntryKind) -> Self {
+        Self {
+            name: name.to_string(),
+            label: label.to_string(),
+            icon_name: None,
+            tooltip: None,
+            accel: None,
+            condns: String::new(),
+            kind,
+        }
+    }
+
+    pub fn item(name: &str, label: &str) -> Self {
+        Self::new(name, label, EntryKind::Item)
+    }
+
+    pub fn check(name: &str, label: &str) -> Self {
+        Self::new(name, label, EntryKind::Check(false))
+    }
+
+    pub fn submenu(name: &str, label: &str, entries: Vec<MenuEntryDefn>) -> Self {
+        Self::new(name, label, EntryKind::Submenu(entries))
+    }
+
+    pub fn separator() -> Self {
+        Self::new("", "", EntryKind::Separator)
+    }
+
+    /// Create an entry from data read at run time (e.g. from a
+    /// configuration file) where `kind` is one of the `menu!` macro's
+    /// keywords: "item", "check", "submenu" or "separator".  `entries`
+    /// are only used by submenus.
+    pub fn from_parts(
+        kind: &str,
+        name: &str,
+        label: &str,
+        entries: Vec<MenuEntryDefn>,
+    ) -> Result<Self, DefnError> {
+        match kind {
+            "item" => Ok(Self::item(name, label)),
+            "check" => Ok(Self::check(name, label)),
+            "submenu" => Ok(Self::submenu(name, label, entries)),
+            "separator" => Ok(Self::separator()),
+            _ => Err(DefnError::UnknownKind(name.to_string(), kind.to_string())),
+        }
+    }
+
+    pub fn icon(mut self, icon_name: &str) -> Self {
+        self.icon_name = Some(icon_name.to_string());
+        self
+    }
+
+    pub fn tooltip(mut self, tooltip: &str) -> Self {
+        self.tooltip = Some(tooltip.to_string());
+        self
+    }
+
+    pub fn accel(mut self, accel: &str) -> Self {
+        self.accel = Some(accel.to_string());
+        self
+    }
+
+    /// The names of the required conditions separated by "|".
+    pub fn condns(mut self, condns: &str) -> Self {
+        self.condns = condns.to_string();
+        self
+    }
+
+    /// The initial state of a check item.
+    pub fn active(mut self, active: bool) -> Self {
+        if let EntryKind::Check(_) = self.kind {
+            self.kind = EntryKind::Check(active);
+        } else {
+            log::warn!("{}: \"active\" only applies to check items", self.name);
+        }
+        self
+    }
+
+    fn menu_item_spec(&self) -> MenuItemSpec {
+        MenuItemSpec(
+            self.label.to_string(),
+            self.icon_name.as_deref().map(IconSource::from),
+            self.tooltip.clone(),
+            self.accel.clone(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MenuDefn(Vec<MenuEntryDefn>);
+
+impl MenuDefn {
+    pub fn new(entries: Vec<MenuEntryDefn>) -> Self {
+        Self(entries)
+    }
+
+    pub fn entries(&self) -> &[MenuEntryDefn] {
+        &self.0
+    }
+
+    /// Check the definition for unknown conditions, duplicate or empty
+    /// names and invalid accelerators reporting all errors found.
+    /// NB: GTK must have been initialised to check accelerators.
+    pub fn validate(&self, condn_names: &CondnNames) -> Result<(), Vec<DefnError>> {
+        self.validate_after(&HashSet::new(), condn_names)
+    }
+
+    // As validate() but top level names must also not be in `names`
+    // (i.e. those of the items that are appended before the definition).
+    pub(crate) fn validate_after(
+        &self,
+        names: &HashSet<&str>,
+        condn_names: &CondnNames,
+    ) -> Result<(), Vec<DefnError>> {
+        let mut errors = vec![];
+        Self::validate_entries(&self.0, "", names.clone(), condn_names, &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn validate_entries<'a>(
+        entries: &'a [MenuEntryDefn],
+        prefix: &str,
+        mut names: HashSet<&'a str>,
+        condn_names: &CondnNames,
+        errors: &mut Vec<DefnError>,
+    ) {
+        for entry in entries.iter() {
+            if let EntryKind::Separator = entry.kind {
+                continue;
+            }
+            let path = format!("{}{}", prefix, entry.name);
+            if entry.name.is_empty() {
+                errors.push(DefnError::EmptyName(path.clone()));
+            } else if !names.insert(&entry.name[..]) {
+                errors.push(DefnError::DuplicateName(path.clone()));
+            }
+            if let Err(condn) = condn_names.evaluate(&entry.condns) {
+                errors.push(DefnError::UnknownCondn(path.clone(), condn));
+            }
+            if let Some(accel) = &entry.accel {
+                if gtk::accelerator_parse(accel).0 == 0 {
+                    errors.push(DefnError::InvalidAccelerator(path.clone(), accel.clone()));
+                }
+            }
+            if let EntryKind::Submenu(sub_entries) = &entry.kind {
+                let prefix = format!("{}/", path);
+                Self::validate_entries(sub_entries, &prefix, HashSet::new(), condn_names, errors);
+            }
+        }
+    }
+}
+
+impl ManagedMenu {
+    /// Append the entries described by `menu_defn` after checking that
+    /// it is valid.  Nothing is appended if it is invalid.
+    pub fn append_definition(
+        &self,
+        menu_defn: &MenuDefn,
+        condn_names: &CondnNames,
+    ) -> Result<(), Vec<DefnError>> {
+        menu_defn.validate(condn_names)?;
+        self.append_entries(&menu_defn.0, "", condn_names)
+            .map_err(|err| vec![err])
+    }
+
+    fn append_entries(
+        &self,
+        entries: &[MenuEntryDefn],
+        prefix: &str,
+        condn_names: &CondnNames,
+    ) -> Result<(), DefnError> {
+        for entry in entries.iter() {
+            let path = format!("{}{}", prefix, entry.name);
+            // NB: validation has already established that this is OK
+            let condns = condn_names.evaluate(&entry.condns).unwrap_or(0);
+            let spec = entry.menu_item_spec();
+            let result = match &entry.kind {
+                EntryKind::Item => self.append_item(&entry.name, &spec, condns).map(|_| ()),
+                EntryKind::Check(active) => self
+                    .append_check_item(&entry.name, &spec, condns, *active)
+                    .map(|_| ()),
+                // NB: appending to the submenu brings the state of its
+                // (initially switched off) item up to date
+                EntryKind::Submenu(sub_entries) => {
+                    match self.append_submenu(&entry.name, &spec, condns) {
+                        Ok(submenu) => {
+                            let prefix = format!("{}/", path);
+                            submenu.append_entries(sub_entries, &prefix, condn_names)?;
+                            Ok(())
+                        }
+                        Err(err) => Err(err),
+                    }
+                }
+                EntryKind::Separator => {
+                    self.append_separator();
+                    Ok(())
+                }
+            };
+            result.map_err(|err| DefnError::Menu(path, err))?;
+        }
+        Ok(())
+    }
+}
+
+/// Create a `MenuDefn` e.g.
+/// ```ignore
+/// let defn = menu! {
+///     item "copy": "_Copy", icon = "edit-copy", accel = "<Ctrl>C", condns = "SELN_MADE";
+///     check "wrap": "_Wrap Lines", active = true;
+///     separator;
+///     submenu "more": "_More" {
+///         item "delete": "_Delete", condns = "SELN_MADE | HOVER_OK";
+///     }
+/// };
+/// ```
+#[macro_export]
+macro_rules! menu {
+    (@entries [$($done:expr,)*]) => {
+        vec![$($done,)*]
+    };
+    (@entries [$($done:expr,)*] separator; $($rest:tt)*) => {
+        $crate::menu!(@entries [
+            $($done,)*
+            $crate::gtkx::menu::definition::MenuEntryDefn::separator(),
+        ] $($rest)*)
+    };
+    (@entries [$($done:expr,)*]
+        item $name:literal : $label:literal $(, $key:ident = $value:literal)* ; $($rest:tt)*
+    ) => {
+        $crate::menu!(@entries [
+            $($done,)*
+            $crate::gtkx::menu::definition::MenuEntryDefn::item($name, $label)$(.$key($value))*,
+        ] $($rest)*)
+    };
+    (@entries [$($done:expr,)*]
+        check $name:literal : $label:literal $(, $key:ident = $value:literal)* ; $($rest:tt)*
+    ) => {
+        $crate::menu!(@entries [
+            $($done,)*
+            $crate::gtkx::menu::definition::MenuEntryDefn::check($name, $label)$(.$key($value))*,
+        ] $($rest)*)
+    };
+    (@entries [$($done:expr,)*]
+        submenu $name:literal : $label:literal $(, $key:ident = $value:literal)*
+        { $($body:tt)* } $($rest:tt)*
+    ) => {
+        $crate::menu!(@entries [
+            $($done,)*
+            $crate::gtkx::menu::definition::MenuEntryDefn::submenu(
+                $name,
+                $label,
+                $crate::menu!(@entries [] $($body)*),
+            )$(.$key($value))*,
+        ] $($rest)*)
+    };
+    ( $($entries:tt)* ) => {
+        $crate::gtkx::menu::definition::MenuDefn::new($crate::menu!(@entries [] $($entries)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sav_state::SAV_NEXT_CONDN;
+
+    #[test]
+    fn condn_expressions_are_evaluated() {
+        let mut names = CondnNames::standard();
+        names.add("DIRTY", SAV_NEXT_CONDN);
+        assert_eq!(names.evaluate(""), Ok(0));
+        assert_eq!(
+            names.evaluate("SELN_MADE | DIRTY"),
+            Ok(SAV_SELN_MADE | SAV_NEXT_CONDN)
+        );
+        assert_eq!(names.evaluate("SELN_MADE|CLEAN"), Err("CLEAN".to_string()));
+    }
+
+    #[test]
+    fn macro_builds_nested_definition() {
+        let defn = menu! {
+            item "copy": "_Copy", condns = "SELN_MADE";
+            separator;
+            check "wrap": "_Wrap", active = true;
+            submenu "more": "_More" {
+                item "delete": "_Delete", tooltip = "Delete them";
+            }
+        };
+        assert_eq!(defn.entries().len(), 4);
+        assert!(defn.validate(&CondnNames::standard()).is_ok());
+        match &defn.entries()[3].kind {
+            EntryKind::Submenu(entries) => assert_eq!(entries[0].name, "delete"),
+            _ => panic!("expected a submenu"),
+        }
+    }
+
+    #[test]
+    fn validation_reports_all_errors() {
+        let defn = menu! {
+            item "copy": "_Copy", condns = "SELN_MADE | NONSENSE";
+            item "copy": "Copy _Again";
+            submenu "more": "_More" {
+                item "": "_Nameless";
+            }
+        };
+        let errors = defn.validate(&CondnNames::standard()).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert!(
+            matches!(&errors[0], DefnError::UnknownCondn(path, condn) if path == "copy" && condn == "NONSENSE")
+        );
+        assert!(matches!(&errors[1], DefnError::DuplicateName(path) if path == "copy"));
+        assert!(matches!(&errors[2], DefnError::EmptyName(path) if path == "more/"));
+    }
+
+    #[test]
+    fn entries_can_be_made_from_data() {
+        let data = [
+            ("item", "copy", "_Copy", "SELN_MADE"),
+            ("check", "wrap", "_Wrap", ""),
+        ];
+        let mut entries = vec![];
+        for (kind, name, label, condns) in data.iter() {
+            let name = name.to_string();
+            entries.push(
+                MenuEntryDefn::from_parts(kind, &name, label, vec![])
+                    .unwrap()
+                    .condns(condns),
+            );
+        }
+        let defn = MenuDefn::new(entries);
+        assert!(defn.validate(&CondnNames::standard()).is_ok());
+        assert_eq!(defn.entries()[0].name, "copy");
+        assert!(matches!(defn.entries()[1].kind, EntryKind::Check(false)));
+        assert!(matches!(
+            MenuEntryDefn::from_parts("button", "go", "_Go", vec![]),
+            Err(DefnError::UnknownKind(name, kind)) if name == "go" && kind == "button"
+        ));
+    }
+}
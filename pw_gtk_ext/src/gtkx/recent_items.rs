@@ -0,0 +1,289 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! A most recently used list of paths (or strings) that is kept in
+//! `recollections` and displayed as a dynamic section of a `ManagedMenu`.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use crate::gtkx::menu::{ManagedMenu, MenuItemSpec};
+use crate::sav_state::{self, MaskedCondns};
+use crate::wrapper::*;
+
+type ActivationCallback = Box<dyn Fn(&str)>;
+
+pub struct RecentItemsMenuCore {
+    menu: ManagedMenu,
+    section: String,
+    key: String,
+    max_items: usize,
+    are_paths: bool,
+    items: RefCell<Vec<String>>,
+    clear_item: Option<gtk::MenuItem>,
+    // The condition that the "Clear list" item requires i.e. that the
+    // list isn't empty (or 0 if there's no such item)
+    not_empty_condn: u64,
+    activation_callbacks: RefCell<Vec<ActivationCallback>>,
+}
+
+#[derive(WClone)]
+pub struct RecentItemsMenu(Rc<RecentItemsMenuCore>);
+
+impl RecentItemsMenu {
+    pub fn items(&self) -> Vec<String> {
+        self.0.items.borrow().clone()
+    }
+
+    /// Call `callback` with the item when one of the items is activated.
+    pub fn connect_activated<F: Fn(&str) + 'static>(&self, callback: F) {
+        self.0
+            .activation_callbacks
+            .borrow_mut()
+            .push(Box::new(callback));
+    }
+
+    /// Move `item` to the top of the list (adding it if necessary).
+    /// NB: items containing line breaks are ignored as the list is
+    /// remembered one item per line.
+    pub fn add_item(&self, item: &str) {
+        if item.contains(|c| c == '\n' || c == '\r') {
+            log::warn!("{}: ignoring item containing a line break", self.0.key);
+            return;
+        }
+        {
+            let mut items = self.0.items.borrow_mut();
+            items.retain(|i| i != item);
+            items.insert(0, item.to_string());
+            items.truncate(self.0.max_items);
+        }
+        self.save_and_update();
+    }
+
+    pub fn add_path<P: AsRef<Path>>(&self, path: P) {
+        match path.as_ref().to_str() {
+            Some(path_str) => self.add_item(path_str),
+            None => log::warn!("{}: ignoring non UTF-8 path", self.0.key),
+        }
+    }
+
+    pub fn remove_item(&self, item: &str) {
+        self.0.items.borrow_mut().retain(|i| i != item);
+        self.save_and_update();
+    }
+
+    pub fn clear(&self) {
+        self.0.items.borrow_mut().clear();
+        self.save_and_update();
+    }
+
+    /// Reload the list from `recollections` (e.g. to pick up changes
+    /// made by another instance) and remove paths that no longer exist.
+    pub fn reload(&self) {
+        *self.0.items.borrow_mut() = Self::recall_items(&self.0.key, self.0.max_items);
+        self.save_and_update();
+    }
+
+    fn recall_items(key: &str, max_items: usize) -> Vec<String> {
+        match recollections::recall(key) {
+            Some(text) => text
+                .lines()
+                .filter(|line| !line.is_empty())
+                .take(max_items)
+                .map(|line| line.to_string())
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn prune(&self) {
+        if self.0.are_paths {
+            self.0
+                .items
+                .borrow_mut()
+                .retain(|item| Path::new(item).exists());
+        }
+    }
+
+    fn save_and_update(&self) {
+        self.prune();
+        recollections::remember(&self.0.key, &self.0.items.borrow().join("\n"));
+        if let Err(err) = self.update_section() {
+            log::error!("{}: error updating menu: {}", self.0.key, err);
+        }
+    }
+
+    fn label_for(&self, index: usize, item: &str) -> String {
+        let text = if self.0.are_paths {
+            match Path::new(item).file_name() {
+                Some(file_name) => file_name.to_string_lossy().to_string(),
+                None => item.to_string(),
+            }
+        } else {
+            item.to_string()
+        };
+        let text = text.replace('_', "__");
+        if index < 9 {
+            format!("_{} {}", index + 1, text)
+        } else {
+            text
+        }
+    }
+
+    fn update_section(&self) -> Result<(), sav_state::Error> {
+        let items = self.0.items.borrow().clone();
+        let menu_items: Vec<(String, MenuItemSpec, u64)> = items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let label = self.label_for(index, item);
+                let spec = MenuItemSpec(label, None, Some(item.to_string()), None);
+                (format!("{}::{}", self.0.section, index), spec, 0)
+            })
+            .collect();
+        self.0.menu.replace_section(&self.0.section, &menu_items)?;
+        for ((name, _, _), item) in menu_items.iter().zip(items.into_iter()) {
+            let menu_item = self.0.menu.menu_item(name)?;
+            let weak_core = Rc::downgrade(&self.0);
+            menu_item.connect_activate(move |_| {
+                if let Some(core) = weak_core.upgrade() {
+                    for callback in core.activation_callbacks.borrow().iter() {
+                        callback(&item)
+                    }
+                }
+            });
+        }
+        if self.0.not_empty_condn != 0 {
+            let condns = if self.0.items.borrow().is_empty() {
+                0
+            } else {
+                self.0.not_empty_condn
+            };
+            self.0
+                .menu
+                .change_notifier()
+                .notify_changed_condns(MaskedCondns {
+                    condns,
+                    mask: self.0.not_empty_condn,
+                });
+        }
+        Ok(())
+    }
+}
+
+pub struct RecentItemsMenuBuilder {
+    key: String,
+    section: String,
+    max_items: usize,
+    are_paths: bool,
+    clear_item_condn: Option<u64>,
+}
+
+impl RecentItemsMenuBuilder {
+    /// The list will be remembered in `recollections` under `key`.
+    pub fn new(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            section: "recent".to_string(),
+            max_items: 10,
+            are_paths: true,
+            clear_item_condn: None,
+        }
+    }
+
+    /// The name of the dynamic section (default "recent").
+    pub fn section(mut self, section: &str) -> Self {
+        self.section = section.to_string();
+        self
+    }
+
+    pub fn max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items;
+        self
+    }
+
+    /// Whether the items are file paths (the default) which are shown
+    /// by file name and pruned when the file no longer exists.
+    pub fn are_paths(mut self, are_paths: bool) -> Self {
+        self.are_paths = are_paths;
+        self
+    }
+
+    /// Append a "Clear list" item after the section that requires
+    /// `not_empty_condn` which is set while the list isn't empty.  The
+    /// condition must be reserved for this purpose by the application
+    /// (e.g. with the change notifier's `allocate_condns()`).
+    pub fn clear_item(mut self, not_empty_condn: u64) -> Self {
+        self.clear_item_condn = Some(not_empty_condn);
+        self
+    }
+
+    /// Append the recent items section (and "Clear list" item) to `menu`.
+    pub fn build(self, menu: &ManagedMenu) -> Result<RecentItemsMenu, sav_state::Error> {
+        menu.append_dynamic_section(&self.section)?;
+        let (clear_item, not_empty_condn) = if let Some(not_empty_condn) = self.clear_item_condn {
+            let name = format!("{}::clear", self.section);
+            let spec: MenuItemSpec = (
+                "C_lear List",
+                None,
+                Some("Remove all items from the recent items list"),
+            )
+                .into();
+            let clear_item = menu.append_item(&name, &spec, not_empty_condn)?;
+            (Some(clear_item), not_empty_condn)
+        } else {
+            (None, 0)
+        };
+        let rim = RecentItemsMenu(Rc::new(RecentItemsMenuCore {
+            menu: menu.clone(),
+            items: RefCell::new(RecentItemsMenu::recall_items(&self.key, self.max_items)),
+            section: self.section,
+            key: self.key,
+            max_items: self.max_items,
+            are_paths: self.are_paths,
+            clear_item,
+            not_empty_condn,
+            activation_callbacks: RefCell::new(vec![]),
+        }));
+        if let Some(clear_item) = &rim.0.clear_item {
+            let weak_core = Rc::downgrade(&rim.0);
+            clear_item.connect_activate(move |_| {
+                if let Some(core) = weak_core.upgrade() {
+                    RecentItemsMenu(core).clear()
+                }
+            });
+        }
+        rim.save_and_update();
+
+        Ok(rim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gtkx::menu::ManagedMenuBuilder;
+    use crate::sav_state::SAV_NEXT_CONDN;
+
+    #[test]
+    fn clear_item_is_only_enabled_when_not_empty() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let menu = ManagedMenuBuilder::new().build();
+        let recent_items = RecentItemsMenuBuilder::new("test::recent_items")
+            .are_paths(false)
+            .clear_item(SAV_NEXT_CONDN)
+            .build(&menu)
+            .unwrap();
+        let clear_item = menu.menu_item("recent::clear").unwrap();
+        assert!(!clear_item.get_sensitive());
+        recent_items.add_item("first");
+        assert!(clear_item.get_sensitive());
+        recent_items.clear();
+        assert!(!clear_item.get_sensitive());
+    }
+}
@@ -0,0 +1,241 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use crate::{
+    gtkx::menu::{owned_items, MenuItemSpec},
+    sav_state::{
+        self, ChangedCondnsNotifier, ConditionalWidgets, ConditionalWidgetsBuilder, MaskedCondns,
+        WidgetStatesControlled,
+    },
+    wrapper::*,
+};
+
+#[derive(PWO)]
+pub struct ManagedToolbarCore {
+    toolbar: gtk::Toolbar,
+    items: ConditionalWidgets<String, gtk::ToolItem>,
+    accel_group: gtk::AccelGroup,
+}
+
+/// A `gtk::Toolbar` whose items are built from the same specs as a
+/// `ManagedMenu`'s and whose states are controlled by conditions.
+#[derive(PWO, WClone)]
+pub struct ManagedToolbar(Rc<ManagedToolbarCore>);
+
+impl ManagedToolbar {
+    pub fn toolbar(&self) -> gtk::Toolbar {
+        self.0.toolbar.clone()
+    }
+
+    /// See `ManagedMenu::accel_group()`.
+    pub fn accel_group(&self) -> &gtk::AccelGroup {
+        &self.0.accel_group
+    }
+
+    pub fn tool_item(&self, name: &str) -> Result<gtk::ToolItem, sav_state::Error> {
+        self.0.items.get_widget(name)
+    }
+
+    pub fn append_tool_item(
+        &self,
+        name: &str,
+        item: &gtk::ToolItem,
+        condns: u64,
+    ) -> Result<(), sav_state::Error> {
+        // NB: show the item before its state is set (rather than showing
+        // the whole toolbar after) so that it stays hidden if it should
+        item.show_all();
+        self.0.items.add_widget(name.to_string(), item, condns)?;
+        self.0.toolbar.insert(item, -1);
+        Ok(())
+    }
+
+    pub fn append_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+    ) -> Result<gtk::ToolButton, sav_state::Error> {
+        let image = menu_item_spec.image(self.0.toolbar.get_icon_size());
+        let button = gtk::ToolButton::new(image.as_ref(), Some(&menu_item_spec.0));
+        button.set_use_underline(true);
+        button.set_tooltip_text(menu_item_spec.2.as_deref());
+        self.append_tool_item(name, button.upcast_ref::<gtk::ToolItem>(), condns)?;
+        if let Some(accel) = menu_item_spec.accelerator() {
+            self.0.items.add_accelerator(
+                name.to_string(),
+                &button,
+                "clicked",
+                &self.0.accel_group,
+                accel,
+            );
+        }
+
+        Ok(button)
+    }
+
+    pub fn append_toggle_item(
+        &self,
+        name: &str,
+        menu_item_spec: &MenuItemSpec,
+        condns: u64,
+        active: bool,
+    ) -> Result<gtk::ToggleToolButton, sav_state::Error> {
+        let button = gtk::ToggleToolButton::new();
+        if let Some(image) = menu_item_spec.image(self.0.toolbar.get_icon_size()) {
+            button.set_icon_widget(Some(&image));
+        }
+        button.set_label(Some(&menu_item_spec.0));
+        button.set_use_underline(true);
+        button.set_tooltip_text(menu_item_spec.2.as_deref());
+        button.set_active(active);
+        self.append_tool_item(name, button.upcast_ref::<gtk::ToolItem>(), condns)?;
+        if let Some(accel) = menu_item_spec.accelerator() {
+            self.0.items.add_accelerator(
+                name.to_string(),
+                &button,
+                "clicked",
+                &self.0.accel_group,
+                accel,
+            );
+        }
+
+        Ok(button)
+    }
+
+    pub fn remove_item(&self, name: &str) -> Result<gtk::ToolItem, sav_state::Error> {
+        let item = self.0.items.remove_widget(name)?;
+        self.0.toolbar.remove(&item);
+        Ok(item)
+    }
+
+    pub fn append_separator(&self) {
+        let separator = gtk::SeparatorToolItem::new();
+        separator.show();
+        self.0.toolbar.insert(&separator, -1);
+    }
+
+    pub fn update_condns(&self, changed_condns: MaskedCondns) {
+        self.0.items.update_condns(changed_condns);
+    }
+
+    pub fn update_hover_condns(&self, hover_ok: bool) {
+        self.0.items.update_hover_condns(hover_ok);
+    }
+}
+
+#[derive(Default)]
+pub struct ManagedToolbarBuilder {
+    conditional_widgets_builder: ConditionalWidgetsBuilder,
+    style: Option<gtk::ToolbarStyle>,
+    accel_group: Option<gtk::AccelGroup>,
+    items: Vec<(String, MenuItemSpec, u64)>,
+}
+
+impl ManagedToolbarBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn widget_states_controlled(
+        &mut self,
+        widget_states_controlled: WidgetStatesControlled,
+    ) -> &mut Self {
+        self.conditional_widgets_builder
+            .widget_states_controlled(widget_states_controlled);
+        self
+    }
+
+    pub fn selection(&mut self, selection: &gtk::TreeSelection) -> &mut Self {
+        self.conditional_widgets_builder.selection(selection);
+        self
+    }
+
+    pub fn change_notifier(&mut self, change_notifier: &ChangedCondnsNotifier) -> &mut Self {
+        self.conditional_widgets_builder
+            .change_notifier(change_notifier);
+        self
+    }
+
+    pub fn style(&mut self, style: gtk::ToolbarStyle) -> &mut Self {
+        self.style = Some(style);
+        self
+    }
+
+    /// See `ManagedMenuBuilder::accel_group()`.
+    pub fn accel_group(&mut self, accel_group: &gtk::AccelGroup) -> &mut Self {
+        self.accel_group = Some(accel_group.clone());
+        self
+    }
+
+    pub fn items(&mut self, items: &[(&str, MenuItemSpec, u64)]) -> &mut Self {
+        self.items = owned_items(items);
+        self
+    }
+
+    pub fn build(&self) -> ManagedToolbar {
+        let toolbar = gtk::ToolbarBuilder::new().build();
+        if let Some(style) = self.style {
+            toolbar.set_style(style);
+        }
+        toolbar.show();
+        let items = self
+            .conditional_widgets_builder
+            .build::<String, gtk::ToolItem>();
+        let accel_group = match &self.accel_group {
+            Some(accel_group) => accel_group.clone(),
+            None => gtk::AccelGroup::new(),
+        };
+        let mtb = ManagedToolbar(Rc::new(ManagedToolbarCore {
+            toolbar,
+            items,
+            accel_group,
+        }));
+        for (name, menu_item_spec, condns) in self.items.iter() {
+            if let Err(err) = mtb.append_item(name, menu_item_spec, *condns) {
+                panic!("Error adding item '{}' to toolbar: {}", name, err);
+            };
+        }
+
+        mtb
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sav_state::SAV_NEXT_CONDN;
+
+    const CONDN: u64 = SAV_NEXT_CONDN;
+
+    #[test]
+    fn accelerators_of_disabled_items_are_refused() {
+        // e.g. there's no display
+        if gtk::init().is_err() {
+            return;
+        }
+        let notifier = ChangedCondnsNotifier::new(0);
+        let toolbar = ManagedToolbarBuilder::new()
+            .change_notifier(&notifier)
+            .items(&[(
+                "copy",
+                MenuItemSpec::from(("_Copy", None, None, Some("<Control>c"))),
+                CONDN,
+            )])
+            .build();
+        let item = toolbar.tool_item("copy").unwrap();
+        // Stands in for the default handler (which also requires the
+        // item to be on screen) and is only reached if not refused.
+        item.connect_can_activate_accel(|_, _| true);
+        // NB: the signal id isn't used by the handlers
+        assert!(!item.can_activate_accel(0));
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: CONDN,
+            mask: CONDN,
+        });
+        assert!(item.can_activate_accel(0));
+    }
+}
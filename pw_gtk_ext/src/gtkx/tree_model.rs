@@ -52,6 +52,32 @@ macro_rules! len_of {
     }};
 }
 
+/// A hashable version of the value in a row's key (id) column.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RowKey {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Str(String),
+}
+
+impl RowKey {
+    /// `None` if the value is unset or its type can't be used as a key.
+    pub fn from_value(value: &glib::Value) -> Option<Self> {
+        match value.type_() {
+            glib::Type::Bool => value.get::<bool>().ok()?.map(RowKey::Bool),
+            glib::Type::I8 => value.get::<i8>().ok()?.map(|v| RowKey::Int(v as i64)),
+            glib::Type::I32 => value.get::<i32>().ok()?.map(|v| RowKey::Int(v as i64)),
+            glib::Type::I64 => value.get::<i64>().ok()?.map(RowKey::Int),
+            glib::Type::U8 => value.get::<u8>().ok()?.map(|v| RowKey::UInt(v as u64)),
+            glib::Type::U32 => value.get::<u32>().ok()?.map(|v| RowKey::UInt(v as u64)),
+            glib::Type::U64 => value.get::<u64>().ok()?.map(RowKey::UInt),
+            glib::Type::String => value.get::<String>().ok()?.map(RowKey::Str),
+            _ => None,
+        }
+    }
+}
+
 pub trait WrappedTreeModel<M: IsA<gtk::TreeModel> + TreeModelRowOps> {
     fn columns() -> Vec<gtk::TreeViewColumn>;
     fn model(&self) -> &M;
@@ -116,3 +142,47 @@ pub trait TreeModelRowOps: TreeModelExt {
 
 impl TreeModelRowOps for gtk::ListStore {}
 impl TreeModelRowOps for gtk::TreeStore {}
+impl TreeModelRowOps for gtk::TreeModelFilter {}
+impl TreeModelRowOps for gtk::TreeModelSort {}
+
+/// Convert `iter` in `model` to the equivalent iter in the model at the
+/// bottom of any stack of `gtk::TreeModelSort`s and `gtk::TreeModelFilter`s.
+pub fn underlying_model_iter(
+    model: &gtk::TreeModel,
+    iter: &gtk::TreeIter,
+) -> (gtk::TreeModel, gtk::TreeIter) {
+    let mut model = model.clone();
+    let mut iter = iter.clone();
+    loop {
+        if let Some(sort) = model.downcast_ref::<gtk::TreeModelSort>() {
+            iter = sort.convert_iter_to_child_iter(&iter);
+            model = sort.get_model();
+        } else if let Some(filter) = model.downcast_ref::<gtk::TreeModelFilter>() {
+            iter = filter.convert_iter_to_child_iter(&iter);
+            match filter.get_model() {
+                Some(child_model) => model = child_model,
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    (model, iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gtk::ToValue;
+
+    #[test]
+    fn row_key_from_value() {
+        assert_eq!(
+            RowKey::from_value(&"id".to_value()),
+            Some(RowKey::Str("id".to_string()))
+        );
+        assert_eq!(RowKey::from_value(&7i32.to_value()), Some(RowKey::Int(7)));
+        assert_eq!(RowKey::from_value(&7u32.to_value()), Some(RowKey::UInt(7)));
+        assert_eq!(RowKey::from_value(&1.5f64.to_value()), None);
+    }
+}
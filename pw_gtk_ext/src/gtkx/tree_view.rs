@@ -5,7 +5,7 @@ use crate::gtk::prelude::{
     GtkMenuItemExt, IsA, TreeModelExt, TreeSelectionExt, TreeViewExt, WidgetExt,
 };
 use crate::gtkx::menu::{ManagedMenu, ManagedMenuBuilder, MenuItemSpec};
-use crate::gtkx::tree_model::{TreeModelRowOps, WrappedTreeModel};
+use crate::gtkx::tree_model::{underlying_model_iter, TreeModelRowOps, WrappedTreeModel};
 use crate::sav_state::MaskedCondns;
 use crate::wrapper::*;
 use std::cell::RefCell;
@@ -38,6 +38,19 @@ impl Deref for TreeViewWithPopup {
 }
 
 impl TreeViewWithPopup {
+    // NB: `id_field` refers to the underlying model when the view's
+    // model is a sorted and/or filtered version of it.
+    fn get_id_value_for_iter(&self, model: &gtk::TreeModel, iter: &gtk::TreeIter) -> Value {
+        let (model, iter) = underlying_model_iter(model, iter);
+        model.get_value(&iter, self.0.id_field)
+    }
+
+    fn get_id_value_for_path(&self, path: &gtk::TreePath) -> Option<Value> {
+        let tree_model = self.0.tree_view.get_model()?;
+        let iter = tree_model.get_iter(path)?;
+        Some(self.get_id_value_for_iter(&tree_model, &iter))
+    }
+
     fn get_id_value_at(&self, posn: (f64, f64)) -> Option<Value> {
         if let Some(location) = self
             .0
@@ -45,19 +58,14 @@ impl TreeViewWithPopup {
             .get_path_at_pos(posn.0 as i32, posn.1 as i32)
         {
             if let Some(path) = location.0 {
-                if let Some(tree_model) = self.0.tree_view.get_model() {
-                    if let Some(iter) = tree_model.get_iter(&path) {
-                        let value = tree_model.get_value(&iter, self.0.id_field);
-                        return Some(value);
-                    }
-                }
+                return self.get_id_value_for_path(&path);
             }
         };
         None
     }
 
-    fn set_selected_id(&self, posn: (f64, f64)) {
-        match self.get_id_value_at(posn) {
+    fn set_selected_id_value(&self, o_value: Option<Value>) {
+        match o_value {
             Some(value) => {
                 *self.0.selected_id.borrow_mut() = Some(value);
                 self.0.popup_menu.update_hover_condns(true);
@@ -69,6 +77,42 @@ impl TreeViewWithPopup {
         }
     }
 
+    fn set_selected_id(&self, posn: (f64, f64)) {
+        self.set_selected_id_value(self.get_id_value_at(posn));
+    }
+
+    // Pop up the menu for the row with the keyboard focus (if any)
+    // treating it as the hovered row.
+    fn keyboard_popup(&self) -> bool {
+        let tree_view = &self.0.tree_view;
+        let (o_path, _) = tree_view.get_cursor();
+        match o_path {
+            Some(path) => {
+                self.set_selected_id_value(self.get_id_value_for_path(&path));
+                let area =
+                    tree_view.get_cell_area(Some(&path), Option::<&gtk::TreeViewColumn>::None);
+                let (x, y) = tree_view.convert_bin_window_to_widget_coords(area.x, area.y);
+                match tree_view.get_window() {
+                    Some(window) => {
+                        let rect = gdk::Rectangle {
+                            x,
+                            y,
+                            width: area.width,
+                            height: area.height,
+                        };
+                        self.0.popup_menu.popup_at_rect(&window, &rect);
+                    }
+                    None => self.0.popup_menu.popup_at_widget(tree_view),
+                }
+            }
+            None => {
+                self.set_selected_id_value(None);
+                self.0.popup_menu.popup_at_widget(tree_view);
+            }
+        }
+        true
+    }
+
     pub fn connect_popup_menu_item<F: Fn(Option<Value>, Vec<Value>) + 'static>(
         &self,
         name: &str,
@@ -89,7 +133,7 @@ impl TreeViewWithPopup {
         let mut selected_ids = vec![];
         for tree_path in tree_paths.iter() {
             if let Some(iter) = store.get_iter(tree_path) {
-                selected_ids.push(store.get_value(&iter, self.0.id_field));
+                selected_ids.push(self.get_id_value_for_iter(&store, &iter));
             }
         }
         if hovered_id.is_some() || !selected_ids.is_empty() {
@@ -289,6 +333,14 @@ impl TreeViewWithPopupBuilder {
                 _ => gtk::Inhibit(false),
             });
 
+        let weak_core = Rc::downgrade(&blv.0);
+        blv.0
+            .tree_view
+            .connect_popup_menu(move |_| match weak_core.upgrade() {
+                Some(core) => TreeViewWithPopup(core).keyboard_popup(),
+                None => false,
+            });
+
         blv
     }
 }
@@ -39,6 +39,7 @@ pub mod glibx;
 pub mod gtkx;
 pub mod printer;
 pub mod sav_state;
+pub mod timeout;
 #[macro_use]
 pub mod wrapper;
 pub mod sample;
@@ -7,20 +7,32 @@
 
 use std::cell::{Cell, RefCell};
 use std::clone::Clone;
-use std::collections::HashMap;
 use std::ops::BitOr;
-use std::rc::Rc;
+use std::rc::{Rc, Weak};
 
+use glib::{IsA, ObjectExt};
 use gtk::{TreeSelection, TreeSelectionExt, WidgetExt};
 
 pub use pw_gtk_ext_derive::*;
 
+pub mod actions;
+pub mod handlers;
+pub mod inspector;
+pub mod items;
+pub mod sources;
+
+pub use handlers::WidgetStateHandler;
+pub use items::{ConditionalItems, StateTarget};
+
 #[derive(Debug)]
 pub enum Error {
     DuplicateKey,
     DuplicateWidget,
     NotFound,
     InconsistentMaskCondns,
+    NoFreeCondns,
+    Unnamed,
+    EmptyGroup,
 }
 
 impl std::fmt::Display for Error {
@@ -31,6 +43,9 @@ impl std::fmt::Display for Error {
             DuplicateWidget => f.write_str("Duplicate widget"),
             NotFound => f.write_str("Not found"),
             InconsistentMaskCondns => f.write_str("Inconsistent mask/conditions"),
+            NoFreeCondns => f.write_str("Insufficient free conditions"),
+            Unnamed => f.write_str("Item has no name"),
+            EmptyGroup => f.write_str("Group has no items"),
         }
     }
 }
@@ -50,6 +65,15 @@ impl MaskedCondns {
     pub fn is_consistent(&self) -> bool {
         self.condns & !self.mask == 0
     }
+
+    /// The combined effect of applying `self` followed by `later`.
+    /// Unlike `|`, conditions in `later`'s mask override those in `self`.
+    pub fn followed_by(&self, later: MaskedCondns) -> MaskedCondns {
+        MaskedCondns {
+            condns: (self.condns & !later.mask) | later.condns,
+            mask: self.mask | later.mask,
+        }
+    }
 }
 
 impl BitOr for MaskedCondns {
@@ -140,13 +164,19 @@ impl MaskedCondnProvider for TreeSelection {
     }
 }
 
-pub type NumberedChangeCallback = (u64, Box<dyn Fn(MaskedCondns)>);
+pub type NumberedChangeCallback = (u64, Rc<dyn Fn(MaskedCondns)>);
 
 #[derive(Default)]
 pub struct ChangedCondnsNotifierCore {
     callbacks: RefCell<Vec<NumberedChangeCallback>>,
     next_token: Cell<u64>,
     current_condns: Cell<u64>,
+    trace_label: RefCell<Option<String>>,
+    batch_depth: Cell<u32>,
+    pending: Cell<Option<MaskedCondns>>,
+    inherited_mask: Cell<u64>,
+    allocated_mask: Cell<u64>,
+    parent_subscription: RefCell<Option<Subscription>>,
 }
 
 #[derive(Default, WClone)]
@@ -159,15 +189,75 @@ impl ChangedCondnsNotifier {
         Self(Rc::new(ccn))
     }
 
+    /// Create a child notifier that sees the conditions in
+    /// `inherited_mask` as notified by `self` (and its ancestors) in
+    /// addition to its own local conditions.  The child's local
+    /// conditions must not overlap `inherited_mask` but are otherwise
+    /// independent of those of `self` and of any other children.
+    pub fn new_child(&self, inherited_mask: u64) -> Self {
+        let child = Self::new(self.current_condns() & inherited_mask);
+        child.0.inherited_mask.set(inherited_mask);
+        child.0.allocated_mask.set(inherited_mask);
+        let subscription = self.subscribe_weak(&child.0, move |core, masked_condns| {
+            let inherited = MaskedCondns {
+                condns: masked_condns.condns & inherited_mask,
+                mask: masked_condns.mask & inherited_mask,
+            };
+            if inherited.mask != 0 {
+                ChangedCondnsNotifier(Rc::clone(core)).notify(inherited)
+            }
+        });
+        *child.0.parent_subscription.borrow_mut() = Some(subscription);
+        child
+    }
+
     pub fn current_condns(&self) -> u64 {
         self.0.current_condns.get()
     }
 
+    /// The conditions inherited from a parent notifier (if any).
+    pub fn inherited_mask(&self) -> u64 {
+        self.0.inherited_mask.get()
+    }
+
+    /// The conditions that are either inherited or have been allocated
+    /// via `allocate_condns()`.
+    pub fn allocated_mask(&self) -> u64 {
+        self.0.allocated_mask.get()
+    }
+
+    /// Allocate `count` contiguous conditions that are neither inherited
+    /// nor previously allocated and return the first of them (e.g. for
+    /// use as the `first_condn` of a condition source).  Conditions below
+    /// `SAV_NEXT_CONDN` are reserved for the selection/hover conditions.
+    pub fn allocate_condns(&self, count: u32) -> Result<u64, Error> {
+        debug_assert!(count > 0 && count < 64);
+        let allocated_mask = self.0.allocated_mask.get();
+        let range_mask = (1u64 << count) - 1;
+        let first = SAV_NEXT_CONDN.trailing_zeros();
+        for shift in first..=(64 - count) {
+            let mask = range_mask << shift;
+            if mask & allocated_mask == 0 {
+                self.0.allocated_mask.set(allocated_mask | mask);
+                return Ok(1 << shift);
+            }
+        }
+        Err(Error::NoFreeCondns)
+    }
+
+    /// Log (at debug level) every change notified if `label` is not `None`.
+    pub fn set_trace_label(&self, label: Option<&str>) {
+        *self.0.trace_label.borrow_mut() = label.map(|label| label.to_string());
+    }
+
     pub fn register_callback(&self, callback: Box<dyn Fn(MaskedCondns)>) -> u64 {
         let token = self.0.next_token.get();
         self.0.next_token.set(token + 1);
 
-        self.0.callbacks.borrow_mut().push((token, callback));
+        self.0
+            .callbacks
+            .borrow_mut()
+            .push((token, Rc::from(callback)));
 
         token
     }
@@ -179,110 +269,192 @@ impl ChangedCondnsNotifier {
         }
     }
 
-    pub fn notify_changed_condns(&self, masked_condns: MaskedCondns) {
-        debug_assert!(masked_condns.is_consistent());
-        for (_, callback) in self.0.callbacks.borrow().iter() {
-            callback(masked_condns)
+    /// Register `callback` and return a `Subscription` that will
+    /// deregister it when dropped.
+    pub fn subscribe<F: Fn(MaskedCondns) + 'static>(&self, callback: F) -> Subscription {
+        let token = self.register_callback(Box::new(callback));
+        Subscription {
+            notifier: Rc::downgrade(&self.0),
+            token,
         }
-        self.0
-            .current_condns
-            .set(masked_condns.condns | (self.0.current_condns.get() & !masked_condns.mask));
     }
-}
 
-#[derive(Debug, Default, Clone, Copy)]
-pub enum WidgetStatesControlled {
-    #[default]
-    Sensitivity,
-    Visibility,
-    Both,
-}
-
-use self::WidgetStatesControlled::*;
+    /// Register a `callback` that only holds a weak reference to
+    /// `target` so that the notifier doesn't keep `target` alive.  The
+    /// callback is skipped once `target` has been dropped.
+    pub fn subscribe_weak<T, F>(&self, target: &Rc<T>, callback: F) -> Subscription
+    where
+        T: 'static,
+        F: Fn(&Rc<T>, MaskedCondns) + 'static,
+    {
+        let weak_target = Rc::downgrade(target);
+        self.subscribe(move |masked_condns| {
+            if let Some(target) = weak_target.upgrade() {
+                callback(&target, masked_condns)
+            }
+        })
+    }
 
-#[derive(Debug, Default)]
-struct ConditionalWidgetGroup<W>
-where
-    W: WidgetExt + Clone + PartialEq,
-{
-    widget_states_controlled: WidgetStatesControlled,
-    widgets: HashMap<String, W>,
-    is_on: bool,
-}
+    pub fn notify_changed_condns(&self, masked_condns: MaskedCondns) {
+        debug_assert!(
+            masked_condns.mask & self.0.inherited_mask.get() == 0,
+            "inherited conditions may only be changed via the parent"
+        );
+        self.notify(masked_condns)
+    }
 
-impl<W> ConditionalWidgetGroup<W>
-where
-    W: WidgetExt + Clone + PartialEq,
-{
-    fn len(&self) -> usize {
-        self.widgets.len()
+    fn notify(&self, masked_condns: MaskedCondns) {
+        debug_assert!(masked_condns.is_consistent());
+        let old_condns = self.0.current_condns.get();
+        let new_condns = masked_condns.condns | (old_condns & !masked_condns.mask);
+        if self.0.batch_depth.get() > 0 {
+            let pending = match self.0.pending.get() {
+                Some(pending) => pending.followed_by(masked_condns),
+                None => masked_condns,
+            };
+            self.0.pending.set(Some(pending));
+            self.0.current_condns.set(new_condns);
+            return;
+        }
+        // Include any changes left pending by a batch that panicked
+        let masked_condns = match self.0.pending.take() {
+            Some(pending) => pending.followed_by(masked_condns),
+            None => masked_condns,
+        };
+        let n_callbacks = self.dispatch(masked_condns);
+        if let Some(label) = self.0.trace_label.borrow().as_ref() {
+            log::debug!(
+                "{}: notified {:#x}/{:#x}: {:#x} -> {:#x} ({} callbacks)",
+                label,
+                masked_condns.condns,
+                masked_condns.mask,
+                old_condns,
+                new_condns,
+                n_callbacks
+            );
+        }
+        self.0.current_condns.set(new_condns);
     }
 
-    fn add_widget(&mut self, name: &str, widget: W) -> Result<(), Error> {
-        if self.widgets.contains_key(name) {
-            Err(Error::DuplicateKey)
-        } else if self.widgets.values().any(|value| value == &widget) {
-            Err(Error::DuplicateWidget)
-        } else {
-            match self.widget_states_controlled {
-                Sensitivity => widget.set_sensitive(self.is_on),
-                Visibility => widget.set_visible(self.is_on),
-                Both => {
-                    widget.set_sensitive(self.is_on);
-                    widget.set_visible(self.is_on);
-                }
-            }
-            if self.widgets.insert(name.to_string(), widget).is_none() {
-                Ok(())
-            } else {
-                Err(Error::DuplicateKey)
-            }
+    fn dispatch(&self, masked_condns: MaskedCondns) -> usize {
+        // NB: take a snapshot so that callbacks may (de)register callbacks
+        let callbacks: Vec<Rc<dyn Fn(MaskedCondns)>> = self
+            .0
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|(_, callback)| Rc::clone(callback))
+            .collect();
+        for callback in callbacks.iter() {
+            callback(masked_condns)
         }
+        callbacks.len()
     }
 
-    fn set_state(&mut self, on: bool) {
-        match self.widget_states_controlled {
-            Sensitivity => {
-                for widget in self.widgets.values() {
-                    widget.set_sensitive(on);
-                }
-            }
-            Visibility => {
-                for widget in self.widgets.values() {
-                    widget.set_visible(on);
-                }
+    /// Run `f` accumulating any changes notified while it runs and then
+    /// pass the merged change to the callbacks in a single notification.
+    /// Batches may be nested in which case the notification is made at
+    /// the end of the outermost batch.
+    pub fn batch<R, F: FnOnce() -> R>(&self, f: F) -> R {
+        // NB: the guard restores the depth even if `f` panics
+        struct BatchGuard<'a>(&'a Cell<u32>);
+
+        impl Drop for BatchGuard<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() - 1);
             }
-            Both => {
-                for widget in self.widgets.values() {
-                    widget.set_sensitive(on);
-                    widget.set_visible(on);
+        }
+
+        let result = {
+            self.0.batch_depth.set(self.0.batch_depth.get() + 1);
+            let _guard = BatchGuard(&self.0.batch_depth);
+            f()
+        };
+        if self.0.batch_depth.get() == 0 {
+            if let Some(pending) = self.0.pending.take() {
+                let n_callbacks = self.dispatch(pending);
+                if let Some(label) = self.0.trace_label.borrow().as_ref() {
+                    log::debug!(
+                        "{}: notified batch {:#x}/{:#x}: -> {:#x} ({} callbacks)",
+                        label,
+                        pending.condns,
+                        pending.mask,
+                        self.0.current_condns.get(),
+                        n_callbacks
+                    );
                 }
             }
         }
-        self.is_on = on
+        result
     }
 }
 
-#[derive(Debug, Default)]
-struct ConditionalWidgetGroupBuilder {
-    widget_states_controlled: WidgetStatesControlled,
+/// A guard for a callback registered with a `ChangedCondnsNotifier`.
+/// The callback is deregistered when the guard is dropped.
+#[must_use = "the callback is deregistered as soon as the Subscription is dropped"]
+pub struct Subscription {
+    notifier: Weak<ChangedCondnsNotifierCore>,
+    token: u64,
 }
 
-impl ConditionalWidgetGroupBuilder {
-    fn new(widget_states_controlled: WidgetStatesControlled) -> Self {
-        Self {
-            widget_states_controlled,
+impl Subscription {
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(core) = self.notifier.upgrade() {
+            ChangedCondnsNotifier(core).deregister_callback(self.token)
         }
     }
+}
 
-    fn build<W>(&self) -> ConditionalWidgetGroup<W>
-    where
-        W: WidgetExt + Clone + PartialEq,
-    {
-        ConditionalWidgetGroup::<W> {
-            widget_states_controlled: self.widget_states_controlled,
-            widgets: HashMap::new(),
-            is_on: false,
+impl std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("token", &self.token)
+            .finish()
+    }
+}
+
+/// The state of the conditions controlling an item.
+#[derive(Debug, Clone)]
+pub struct CondnsReport {
+    pub key: String,
+    pub required_condns: u64,
+    pub current_condns: u64,
+    pub is_on: bool,
+}
+
+impl CondnsReport {
+    /// The required conditions that are not currently satisfied.
+    pub fn missing_condns(&self) -> u64 {
+        self.required_condns & !self.current_condns
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum WidgetStatesControlled {
+    #[default]
+    Sensitivity,
+    Visibility,
+    Both,
+}
+
+use self::WidgetStatesControlled::*;
+
+impl WidgetStatesControlled {
+    /// Switch the controlled state(s) of `widget` on or off.
+    pub fn apply<W: WidgetExt>(&self, widget: &W, on: bool) {
+        match self {
+            Sensitivity => widget.set_sensitive(on),
+            Visibility => widget.set_visible(on),
+            Both => {
+                widget.set_sensitive(on);
+                widget.set_visible(on);
+            }
         }
     }
 }
@@ -295,11 +467,11 @@ pub struct ConditionalWidgetGroupsCore<W>
 where
     W: WidgetExt + Clone + PartialEq,
 {
-    conditional_widget_group_builder: ConditionalWidgetGroupBuilder,
-    groups: RefCell<HashMap<u64, ConditionalWidgetGroup<W>>>,
-    current_condns: Cell<u64>,
+    widget_states_controlled: WidgetStatesControlled,
+    items: RefCell<ConditionalItems<String, ControlledWidget<W>>>,
     change_notifier: ChangedCondnsNotifier,
     selection: Option<TreeSelection>,
+    subscription: RefCell<Option<Subscription>>,
 }
 
 #[derive(Default, WClone)]
@@ -346,24 +518,28 @@ impl ConditionalWidgetGroupsBuilder {
         let initial_condns = change_notifier.current_condns();
         let selection = self.selection.as_ref().cloned();
         let cwg = ConditionalWidgetGroups(Rc::new(ConditionalWidgetGroupsCore::<W> {
-            conditional_widget_group_builder: ConditionalWidgetGroupBuilder::new(
-                self.widget_states_controlled,
-            ),
-            groups: RefCell::new(HashMap::new()),
-            current_condns: Cell::new(initial_condns),
+            widget_states_controlled: self.widget_states_controlled,
+            items: RefCell::new(ConditionalItems::new(initial_condns)),
             change_notifier,
             selection,
+            subscription: RefCell::new(None),
         }));
         if let Some(selection) = &self.selection {
             cwg.update_condns(selection.get_masked_conditions());
-            let cwg_clone = cwg.clone();
-            selection
-                .connect_changed(move |seln| cwg_clone.update_condns(seln.get_masked_conditions()));
+            let weak_core = Rc::downgrade(&cwg.0);
+            selection.connect_changed(move |seln| {
+                if let Some(core) = weak_core.upgrade() {
+                    ConditionalWidgetGroups(core).update_condns(seln.get_masked_conditions())
+                }
+            });
         }
-        let cwg_clone = cwg.clone();
-        cwg.0
+        let subscription = cwg
+            .0
             .change_notifier
-            .register_callback(Box::new(move |condns| cwg_clone.update_condns(condns)));
+            .subscribe_weak(&cwg.0, |core, condns| {
+                ConditionalWidgetGroups(Rc::clone(core)).update_condns(condns)
+            });
+        *cwg.0.subscription.borrow_mut() = Some(subscription);
         cwg
     }
 }
@@ -377,15 +553,11 @@ where
     }
 
     pub fn len(&self) -> usize {
-        let mut len = 0;
-        for group in self.0.groups.borrow().values() {
-            len += group.len()
-        }
-        len
+        self.0.items.borrow().len()
     }
 
     pub fn current_condns(&self) -> u64 {
-        self.0.current_condns.get()
+        self.0.items.borrow().current_condns()
     }
 
     pub fn change_notifier(&self) -> &ChangedCondnsNotifier {
@@ -393,38 +565,37 @@ where
     }
 
     pub fn add_widget(&self, name: &str, widget: &W, condns: u64) -> Result<(), Error> {
-        let mut groups = self.0.groups.borrow_mut();
-        if let Some(group) = groups.get_mut(&condns) {
-            group.add_widget(name, widget.clone())?;
-            return Ok(());
-        }
-        let mut group = self.0.conditional_widget_group_builder.build::<W>();
-        group.set_state((condns & self.0.current_condns.get()) == condns);
-        group.add_widget(name, widget.clone())?;
-        groups.insert(condns, group);
+        let controlled_widget = ControlledWidget {
+            widget: widget.clone(),
+            widget_states_controlled: self.0.widget_states_controlled,
+            handler: None,
+        };
+        // NB: release the borrow before setting the widget's state
+        let on = self.0.items.borrow_mut().insert_item(
+            name.to_string(),
+            controlled_widget.clone(),
+            condns,
+        )?;
+        controlled_widget.apply_state(on);
         Ok(())
     }
 
     pub fn get_widget(&self, name: &str) -> Result<W, Error> {
-        let groups = self.0.groups.borrow();
-        for group in groups.values() {
-            if let Some(widget) = group.widgets.get(name) {
-                return Ok(widget.clone());
-            }
+        match self.0.items.borrow().get_item(name) {
+            Some(controlled_widget) => Ok(controlled_widget.widget.clone()),
+            None => Err(Error::NotFound),
         }
-        Err(Error::NotFound)
     }
 
     pub fn update_condns(&self, changed_condns: MaskedCondns) {
-        assert!(changed_condns.is_consistent());
-        let new_condns =
-            changed_condns.condns | (self.0.current_condns.get() & !changed_condns.mask);
-        for (key_condns, group) in self.0.groups.borrow_mut().iter_mut() {
-            if changed_condns.mask & key_condns != 0 {
-                group.set_state((key_condns & new_condns) == *key_condns);
-            };
+        let changes = self
+            .0
+            .items
+            .borrow_mut()
+            .update_condns_deferred(changed_condns);
+        for (controlled_widget, on) in changes {
+            controlled_widget.apply_state(on);
         }
-        self.0.current_condns.set(new_condns)
     }
 
     pub fn update_hover_condns(&self, hover_ok: bool) {
@@ -437,106 +608,65 @@ where
     }
 }
 
-#[derive(Debug)]
-struct ConditionalWidgetHashMap<K, W>
+/// A widget together with the means of controlling its state.
+#[derive(Debug, Clone)]
+struct ControlledWidget<W>
 where
     W: WidgetExt + Clone + PartialEq,
-    K: Eq + std::hash::Hash + std::fmt::Debug,
 {
+    widget: W,
     widget_states_controlled: WidgetStatesControlled,
-    widgets: HashMap<K, W>,
-    is_on: bool,
+    handler: Option<WidgetStateHandler<W>>,
 }
 
-impl<K, W> Default for ConditionalWidgetHashMap<K, W>
+impl<W> PartialEq for ControlledWidget<W>
 where
     W: WidgetExt + Clone + PartialEq,
-    K: Eq + std::hash::Hash + std::fmt::Debug,
 {
-    fn default() -> Self {
-        Self {
-            widget_states_controlled: WidgetStatesControlled::default(),
-            is_on: false,
-            widgets: HashMap::new(),
-        }
+    fn eq(&self, other: &Self) -> bool {
+        self.widget == other.widget
     }
 }
 
-impl<K, W> ConditionalWidgetHashMap<K, W>
+impl<W> StateTarget for ControlledWidget<W>
 where
     W: WidgetExt + Clone + PartialEq,
-    K: Eq + std::hash::Hash + std::fmt::Debug,
 {
-    fn new(widget_states_controlled: WidgetStatesControlled) -> Self {
-        Self {
-            widget_states_controlled,
-            ..std::default::Default::default()
-        }
-        //let mut cwhm = Self::default();
-        //cwhm.widget_states_controlled = widget_states_controlled;
-        //cwhm
-    }
-
-    fn len(&self) -> usize {
-        self.widgets.len()
-    }
-
-    fn insert(&mut self, key: K, widget: W) -> Result<(), Error> {
-        if self.widgets.contains_key(&key) {
-            Err(Error::DuplicateKey)
-        } else if self.widgets.values().any(|value| value == &widget) {
-            Err(Error::DuplicateWidget)
+    fn apply_state(&self, on: bool) {
+        if let Some(handler) = &self.handler {
+            handler.apply(&self.widget, on);
         } else {
-            match self.widget_states_controlled {
-                Sensitivity => widget.set_sensitive(self.is_on),
-                Visibility => widget.set_visible(self.is_on),
-                Both => {
-                    widget.set_sensitive(self.is_on);
-                    widget.set_visible(self.is_on);
-                }
-            }
-            if self.widgets.insert(key, widget).is_none() {
-                Ok(())
-            } else {
-                Err(Error::DuplicateKey)
-            }
-        }
-    }
-
-    fn set_state(&mut self, on: bool) {
-        match self.widget_states_controlled {
-            Sensitivity => {
-                for widget in self.widgets.values() {
-                    widget.set_sensitive(on);
-                }
-            }
-            Visibility => {
-                for widget in self.widgets.values() {
-                    widget.set_visible(on);
-                }
-            }
-            Both => {
-                for widget in self.widgets.values() {
-                    widget.set_sensitive(on);
-                    widget.set_visible(on);
-                }
-            }
+            self.widget_states_controlled.apply(&self.widget, on);
         }
-        self.is_on = on
     }
 }
 
-#[derive(Default)]
 pub struct ConditionalWidgetsCore<K, W>
 where
     W: WidgetExt + Clone + PartialEq,
     K: Eq + std::hash::Hash + std::fmt::Debug,
 {
     widget_states_controlled: WidgetStatesControlled,
-    groups: RefCell<HashMap<u64, ConditionalWidgetHashMap<K, W>>>,
-    current_condns: Cell<u64>,
+    items: RefCell<ConditionalItems<K, ControlledWidget<W>>>,
     change_notifier: ChangedCondnsNotifier,
     selection: Option<TreeSelection>,
+    subscription: RefCell<Option<Subscription>>,
+}
+
+impl<K, W> Default for ConditionalWidgetsCore<K, W>
+where
+    W: WidgetExt + Clone + PartialEq,
+    K: Eq + std::hash::Hash + std::fmt::Debug,
+{
+    fn default() -> Self {
+        Self {
+            widget_states_controlled: WidgetStatesControlled::default(),
+            items: RefCell::new(ConditionalItems::default()),
+            change_notifier: ChangedCondnsNotifier::default(),
+            selection: None,
+            subscription: RefCell::new(None),
+        }
+    }
 }
 
 #[derive(Default, WClone)]
@@ -555,23 +685,131 @@ where
     }
 
     pub fn len(&self) -> usize {
-        let mut len = 0;
-        for group in self.0.groups.borrow().values() {
-            len += group.len()
+        self.0.items.borrow().len()
+    }
+
+    pub fn current_condns(&self) -> u64 {
+        self.0.items.borrow().current_condns()
+    }
+
+    pub fn change_notifier(&self) -> &ChangedCondnsNotifier {
+        &self.0.change_notifier
+    }
+
+    pub fn widget_states_controlled(&self) -> WidgetStatesControlled {
+        self.0.widget_states_controlled
+    }
+
+    pub fn selection(&self) -> Option<&TreeSelection> {
+        self.0.selection.as_ref()
+    }
+
+    pub fn keys(&self) -> Vec<K>
+    where
+        K: Clone,
+    {
+        self.0.items.borrow().keys().cloned().collect()
+    }
+
+    /// Whether the conditions required by the widget with the given
+    /// `key` are currently satisfied.
+    pub fn is_on<Q: ?Sized>(&self, key: &Q) -> Option<bool>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        self.0.items.borrow().is_on(key)
+    }
+
+    /// Add the accelerator `accel` (in `gtk::accelerator_parse()` format)
+    /// for `signal` on `widget` to `accel_group`.  The accelerator is
+    /// refused while the widget with the given `key` is switched off.
+    pub fn add_accelerator<A: IsA<gtk::Widget>>(
+        &self,
+        key: K,
+        widget: &A,
+        signal: &str,
+        accel_group: &gtk::AccelGroup,
+        accel: &str,
+    ) where
+        K: 'static,
+        W: 'static,
+    {
+        let (accel_key, accel_mods) = gtk::accelerator_parse(accel);
+        if accel_key == 0 {
+            log::warn!("{:?}: invalid accelerator: {}", key, accel);
+            return;
         }
-        len
+        widget.add_accelerator(
+            signal,
+            accel_group,
+            accel_key,
+            accel_mods,
+            gtk::AccelFlags::VISIBLE,
+        );
+        let weak_core = Rc::downgrade(&self.0);
+        widget.connect_can_activate_accel(move |widget, _| {
+            let is_on = match weak_core.upgrade() {
+                Some(core) => core.items.borrow().is_on(&key) == Some(true),
+                None => false,
+            };
+            if !is_on {
+                widget.stop_signal_emission("can-activate-accel");
+            }
+            // NB: returning true would skip the default handler's checks
+            // (e.g. that the widget's enclosing menus are usable)
+            false
+        });
+    }
+
+    /// Describe the conditions required by each of the widgets and
+    /// whether they are currently satisfied.
+    pub fn condns_report(&self) -> Vec<CondnsReport> {
+        self.0.items.borrow().condns_report()
     }
 
     pub fn add_widget(&self, key: K, widget: &W, condns: u64) -> Result<(), Error> {
-        let mut groups = self.0.groups.borrow_mut();
-        if let Some(group) = groups.get_mut(&condns) {
-            group.insert(key, widget.clone())?;
-            return Ok(());
-        }
-        let mut group = ConditionalWidgetHashMap::<K, W>::new(self.0.widget_states_controlled);
-        group.set_state((condns & self.0.current_condns.get()) == condns);
-        group.insert(key, widget.clone())?;
-        groups.insert(condns, group);
+        let controlled_widget = ControlledWidget {
+            widget: widget.clone(),
+            widget_states_controlled: self.0.widget_states_controlled,
+            handler: None,
+        };
+        self.insert_controlled_widget(key, controlled_widget, condns)
+    }
+
+    /// Add `widget` with its response to changes in the state of
+    /// `condns` determined by `handler` rather than by the
+    /// `WidgetStatesControlled` setting.
+    pub fn add_widget_with_handler(
+        &self,
+        key: K,
+        widget: &W,
+        condns: u64,
+        handler: WidgetStateHandler<W>,
+    ) -> Result<(), Error> {
+        let controlled_widget = ControlledWidget {
+            widget: widget.clone(),
+            widget_states_controlled: self.0.widget_states_controlled,
+            handler: Some(handler),
+        };
+        self.insert_controlled_widget(key, controlled_widget, condns)
+    }
+
+    // NB: the widget's state is set after the borrow of the items has
+    // been released as its handler may cause the conditions to change
+    // (e.g. toggling a button that is the source of some conditions).
+    fn insert_controlled_widget(
+        &self,
+        key: K,
+        controlled_widget: ControlledWidget<W>,
+        condns: u64,
+    ) -> Result<(), Error> {
+        let on = self
+            .0
+            .items
+            .borrow_mut()
+            .insert_item(key, controlled_widget.clone(), condns)?;
+        controlled_widget.apply_state(on);
         Ok(())
     }
 
@@ -580,25 +818,33 @@ where
         K: std::borrow::Borrow<Q>,
         Q: std::hash::Hash + Eq,
     {
-        let groups = self.0.groups.borrow();
-        for group in groups.values() {
-            if let Some(widget) = group.widgets.get(key) {
-                return Ok(widget.clone());
-            }
+        match self.0.items.borrow().get_item(key) {
+            Some(controlled_widget) => Ok(controlled_widget.widget.clone()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    pub fn remove_widget<Q: ?Sized>(&self, key: &Q) -> Result<W, Error>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: std::hash::Hash + Eq,
+    {
+        match self.0.items.borrow_mut().remove_item(key) {
+            Some(controlled_widget) => Ok(controlled_widget.widget),
+            None => Err(Error::NotFound),
         }
-        Err(Error::NotFound)
     }
 
     pub fn update_condns(&self, changed_condns: MaskedCondns) {
-        debug_assert!(changed_condns.is_consistent());
-        let new_condns =
-            changed_condns.condns | (self.0.current_condns.get() & !changed_condns.mask);
-        for (key_condns, group) in self.0.groups.borrow_mut().iter_mut() {
-            if changed_condns.mask & key_condns != 0 {
-                group.set_state((key_condns & new_condns) == *key_condns);
-            };
+        // NB: release the borrow before applying the changes (see above)
+        let changes = self
+            .0
+            .items
+            .borrow_mut()
+            .update_condns_deferred(changed_condns);
+        for (controlled_widget, on) in changes {
+            controlled_widget.apply_state(on);
         }
-        self.0.current_condns.set(new_condns)
     }
 
     pub fn update_hover_condns(&self, hover_ok: bool) {
@@ -615,6 +861,7 @@ pub struct ConditionalWidgetsBuilder {
     widget_states_controlled: WidgetStatesControlled,
     change_notifier: ChangedCondnsNotifier,
     selection: Option<TreeSelection>,
+    trace_label: Option<String>,
 }
 
 impl Default for ConditionalWidgetsBuilder {
@@ -623,6 +870,7 @@ impl Default for ConditionalWidgetsBuilder {
             widget_states_controlled: WidgetStatesControlled::default(),
             change_notifier: ChangedCondnsNotifier::new(0),
             selection: None,
+            trace_label: None,
         }
     }
 }
@@ -650,31 +898,196 @@ impl ConditionalWidgetsBuilder {
         self
     }
 
+    /// Log (at debug level) every change of conditions using `label`
+    /// to identify the source.
+    pub fn trace_label(&mut self, label: &str) -> &mut Self {
+        self.trace_label = Some(label.to_string());
+        self
+    }
+
     pub fn build<K, W>(&self) -> ConditionalWidgets<K, W>
     where
         W: WidgetExt + Clone + PartialEq,
         K: Eq + std::hash::Hash + std::fmt::Debug + 'static,
     {
         let change_notifier = self.change_notifier.clone();
-        let initial_condns = change_notifier.current_condns();
+        let mut items = ConditionalItems::new(change_notifier.current_condns());
+        items.set_trace_label(self.trace_label.as_deref());
         let selection = self.selection.clone();
         let cwg = ConditionalWidgets(Rc::new(ConditionalWidgetsCore::<K, W> {
             widget_states_controlled: self.widget_states_controlled,
-            groups: RefCell::new(HashMap::new()),
-            current_condns: Cell::new(initial_condns),
+            items: RefCell::new(items),
             change_notifier,
             selection,
+            subscription: RefCell::new(None),
         }));
         if let Some(selection) = &cwg.0.selection {
             cwg.update_condns(selection.get_masked_conditions());
-            let cwg_clone = cwg.clone();
-            selection
-                .connect_changed(move |seln| cwg_clone.update_condns(seln.get_masked_conditions()));
+            let weak_core = Rc::downgrade(&cwg.0);
+            selection.connect_changed(move |seln| {
+                if let Some(core) = weak_core.upgrade() {
+                    ConditionalWidgets(core).update_condns(seln.get_masked_conditions())
+                }
+            });
         }
-        let cwg_clone = cwg.clone();
-        cwg.0
+        let subscription = cwg
+            .0
             .change_notifier
-            .register_callback(Box::new(move |condns| cwg_clone.update_condns(condns)));
+            .subscribe_weak(&cwg.0, |core, condns| {
+                ConditionalWidgets(Rc::clone(core)).update_condns(condns)
+            });
+        *cwg.0.subscription.borrow_mut() = Some(subscription);
         cwg
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscription_deregisters_on_drop() {
+        let notifier = ChangedCondnsNotifier::new(0);
+        let count = Rc::new(Cell::new(0));
+        let count_c = Rc::clone(&count);
+        let subscription = notifier.subscribe(move |_| count_c.set(count_c.get() + 1));
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: SAV_HOVER_OK,
+            mask: SAV_HOVER_MASK,
+        });
+        assert_eq!(count.get(), 1);
+        drop(subscription);
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: SAV_HOVER_NOT_OK,
+            mask: SAV_HOVER_MASK,
+        });
+        assert_eq!(count.get(), 1);
+        assert_eq!(notifier.current_condns(), SAV_HOVER_NOT_OK);
+    }
+
+    #[test]
+    fn weak_subscription_does_not_keep_target_alive() {
+        let notifier = ChangedCondnsNotifier::new(0);
+        let target = Rc::new(Cell::new(0u64));
+        let _subscription = notifier.subscribe_weak(&target, |target, masked_condns| {
+            target.set(masked_condns.condns)
+        });
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: SAV_HOVER_OK,
+            mask: SAV_HOVER_MASK,
+        });
+        assert_eq!(target.get(), SAV_HOVER_OK);
+        let weak_target = Rc::downgrade(&target);
+        drop(target);
+        assert!(weak_target.upgrade().is_none());
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: SAV_HOVER_NOT_OK,
+            mask: SAV_HOVER_MASK,
+        });
+    }
+
+    #[test]
+    fn batch_merges_changes_into_one_notification() {
+        let notifier = ChangedCondnsNotifier::new(SAV_SELN_NONE);
+        let received = Rc::new(RefCell::new(vec![]));
+        let received_c = Rc::clone(&received);
+        let _subscription =
+            notifier.subscribe(move |masked_condns| received_c.borrow_mut().push(masked_condns));
+        let result = notifier.batch(|| {
+            notifier.notify_changed_condns(MaskedCondns {
+                condns: SAV_HOVER_OK,
+                mask: SAV_HOVER_MASK,
+            });
+            notifier.batch(|| {
+                notifier.notify_changed_condns(MaskedCondns {
+                    condns: SAV_SELN_MADE | SAV_SELN_UNIQUE,
+                    mask: SAV_SELN_MASK,
+                })
+            });
+            notifier.notify_changed_condns(MaskedCondns {
+                condns: SAV_HOVER_NOT_OK,
+                mask: SAV_HOVER_MASK,
+            });
+            assert!(received.borrow().is_empty());
+            42
+        });
+        assert_eq!(result, 42);
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].condns,
+            SAV_SELN_MADE | SAV_SELN_UNIQUE | SAV_HOVER_NOT_OK
+        );
+        assert_eq!(received[0].mask, SAV_SELN_MASK | SAV_HOVER_MASK);
+        assert_eq!(
+            notifier.current_condns(),
+            SAV_SELN_MADE | SAV_SELN_UNIQUE | SAV_HOVER_NOT_OK
+        );
+    }
+
+    #[test]
+    fn panicking_batch_does_not_block_notifications() {
+        let notifier = ChangedCondnsNotifier::new(0);
+        let received = Rc::new(RefCell::new(vec![]));
+        let received_c = Rc::clone(&received);
+        let _subscription =
+            notifier.subscribe(move |masked_condns| received_c.borrow_mut().push(masked_condns));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            notifier.batch(|| {
+                notifier.notify_changed_condns(MaskedCondns {
+                    condns: SAV_SELN_NONE,
+                    mask: SAV_SELN_MASK,
+                });
+                panic!("oops")
+            })
+        }));
+        assert!(result.is_err());
+        assert!(received.borrow().is_empty());
+        notifier.notify_changed_condns(MaskedCondns {
+            condns: SAV_HOVER_OK,
+            mask: SAV_HOVER_MASK,
+        });
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].condns, SAV_SELN_NONE | SAV_HOVER_OK);
+        assert_eq!(received[0].mask, SAV_SELN_MASK | SAV_HOVER_MASK);
+    }
+
+    #[test]
+    fn child_notifier_sees_parent_and_local_condns() {
+        let parent = ChangedCondnsNotifier::new(0);
+        let app_condn = parent.allocate_condns(2).unwrap();
+        let app_mask = app_condn | app_condn << 1;
+        let child_a = parent.new_child(app_mask);
+        let child_b = parent.new_child(app_mask);
+        let local_a = child_a.allocate_condns(1).unwrap();
+        let local_b = child_b.allocate_condns(1).unwrap();
+        assert_eq!(local_a, local_b);
+        assert_eq!(local_a & app_mask, 0);
+
+        let seen_by_a = Rc::new(Cell::new(0));
+        let seen_by_a_c = Rc::clone(&seen_by_a);
+        let _subscription = child_a.subscribe(move |masked_condns| {
+            seen_by_a_c.set(masked_condns.condns | (seen_by_a_c.get() & !masked_condns.mask))
+        });
+        child_a.notify_changed_condns(MaskedCondns {
+            condns: local_a,
+            mask: local_a,
+        });
+        parent.notify_changed_condns(MaskedCondns {
+            condns: app_condn,
+            mask: app_mask | SAV_HOVER_MASK,
+        });
+        assert_eq!(seen_by_a.get(), app_condn | local_a);
+        assert_eq!(child_a.current_condns(), app_condn | local_a);
+        assert_eq!(child_b.current_condns(), app_condn);
+        assert_eq!(parent.current_condns(), app_condn);
+
+        drop(child_b);
+        parent.notify_changed_condns(MaskedCondns {
+            condns: app_condn << 1,
+            mask: app_mask,
+        });
+        assert_eq!(child_a.current_condns(), app_condn << 1 | local_a);
+    }
+}
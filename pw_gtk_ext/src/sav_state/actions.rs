@@ -0,0 +1,162 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Enable/disable `gio::SimpleAction`s according to the current
+//! conditions so that menus, tool bars and keyboard shortcuts bound
+//! to the same action share a single enablement state.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gio::prelude::*;
+use glib::Cast;
+
+use super::{
+    ChangedCondnsNotifier, ConditionalItems, Error, MaskedCondns, StateTarget, Subscription,
+};
+use pw_gtk_ext_derive::WClone;
+
+impl StateTarget for gio::SimpleAction {
+    fn apply_state(&self, on: bool) {
+        self.set_enabled(on)
+    }
+}
+
+pub struct ConditionalActionsCore {
+    action_map: gio::ActionMap,
+    items: RefCell<ConditionalItems<String, gio::SimpleAction>>,
+    change_notifier: ChangedCondnsNotifier,
+    subscription: RefCell<Option<Subscription>>,
+}
+
+#[derive(WClone)]
+pub struct ConditionalActions(Rc<ConditionalActionsCore>);
+
+impl ConditionalActions {
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.items.borrow().len()
+    }
+
+    pub fn current_condns(&self) -> u64 {
+        self.0.items.borrow().current_condns()
+    }
+
+    pub fn change_notifier(&self) -> &ChangedCondnsNotifier {
+        &self.0.change_notifier
+    }
+
+    /// The action map that the actions are added to.  Unless one was
+    /// supplied to the builder this will be a `gio::SimpleActionGroup`
+    /// suitable for use with `WidgetExt::insert_action_group()`.
+    pub fn action_map(&self) -> &gio::ActionMap {
+        &self.0.action_map
+    }
+
+    /// Add `action` to the action map with its enablement controlled
+    /// by `condns`.
+    pub fn add_simple_action(&self, action: &gio::SimpleAction, condns: u64) -> Result<(), Error> {
+        let name = match action.get_name() {
+            Some(name) => name.to_string(),
+            None => return Err(Error::Unnamed),
+        };
+        // NB: the state is set after the borrow of the items has been
+        // released as "notify::enabled" handlers may access the actions
+        let on = self
+            .0
+            .items
+            .borrow_mut()
+            .insert_item(name, action.clone(), condns)?;
+        action.apply_state(on);
+        self.0.action_map.add_action(action);
+        Ok(())
+    }
+
+    /// Create a parameterless `gio::SimpleAction` called `name` and add
+    /// it to the action map with its enablement controlled by `condns`.
+    pub fn add_action(&self, name: &str, condns: u64) -> Result<gio::SimpleAction, Error> {
+        let action = gio::SimpleAction::new(name, None);
+        self.add_simple_action(&action, condns)?;
+        Ok(action)
+    }
+
+    pub fn get_action(&self, name: &str) -> Result<gio::SimpleAction, Error> {
+        match self.0.items.borrow().get_item(name) {
+            Some(action) => Ok(action.clone()),
+            None => Err(Error::NotFound),
+        }
+    }
+
+    pub fn remove_action(&self, name: &str) -> Result<gio::SimpleAction, Error> {
+        match self.0.items.borrow_mut().remove_item(name) {
+            Some(action) => {
+                self.0.action_map.remove_action(name);
+                Ok(action)
+            }
+            None => Err(Error::NotFound),
+        }
+    }
+
+    pub fn update_condns(&self, changed_condns: MaskedCondns) {
+        // NB: release the borrow before applying the changes (see above)
+        let changes = self
+            .0
+            .items
+            .borrow_mut()
+            .update_condns_deferred(changed_condns);
+        for (action, on) in changes {
+            action.apply_state(on);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ConditionalActionsBuilder {
+    action_map: Option<gio::ActionMap>,
+    change_notifier: Option<ChangedCondnsNotifier>,
+}
+
+impl ConditionalActionsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add actions to `action_map` (e.g. a `gtk::Application` or
+    /// `gtk::ApplicationWindow`) instead of a new `gio::SimpleActionGroup`.
+    pub fn action_map<A: IsA<gio::ActionMap>>(&mut self, action_map: &A) -> &mut Self {
+        self.action_map = Some(action_map.clone().upcast::<gio::ActionMap>());
+        self
+    }
+
+    pub fn change_notifier(&mut self, change_notifier: &ChangedCondnsNotifier) -> &mut Self {
+        self.change_notifier = Some(change_notifier.clone());
+        self
+    }
+
+    pub fn build(&self) -> ConditionalActions {
+        let action_map = if let Some(action_map) = &self.action_map {
+            action_map.clone()
+        } else {
+            gio::SimpleActionGroup::new().upcast::<gio::ActionMap>()
+        };
+        let change_notifier = if let Some(change_notifier) = &self.change_notifier {
+            change_notifier.clone()
+        } else {
+            ChangedCondnsNotifier::new(0)
+        };
+        let items = ConditionalItems::new(change_notifier.current_condns());
+        let ca = ConditionalActions(Rc::new(ConditionalActionsCore {
+            action_map,
+            items: RefCell::new(items),
+            change_notifier,
+            subscription: RefCell::new(None),
+        }));
+        let subscription = ca.0.change_notifier.subscribe_weak(&ca.0, |core, condns| {
+            ConditionalActions(Rc::clone(core)).update_condns(condns)
+        });
+        *ca.0.subscription.borrow_mut() = Some(subscription);
+        ca
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Per widget handlers that determine how a widget reacts when the
+//! conditions it depends on become (un)satisfied.  They allow widget
+//! properties other than sensitivity and visibility to be controlled.
+
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+pub struct WidgetStateHandler<W>(Rc<dyn Fn(&W, bool)>);
+
+impl<W> Clone for WidgetStateHandler<W> {
+    fn clone(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+}
+
+impl<W> std::fmt::Debug for WidgetStateHandler<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("WidgetStateHandler")
+    }
+}
+
+impl<W: 'static> WidgetStateHandler<W> {
+    pub fn new<F: Fn(&W, bool) + 'static>(handler: F) -> Self {
+        Self(Rc::new(handler))
+    }
+
+    pub fn apply(&self, widget: &W, on: bool) {
+        (self.0)(widget, on)
+    }
+
+    /// A handler that applies `self` followed by `other`.
+    pub fn and(self, other: Self) -> Self {
+        Self::new(move |widget, on| {
+            self.apply(widget, on);
+            other.apply(widget, on);
+        })
+    }
+}
+
+impl<W: WidgetExt + 'static> WidgetStateHandler<W> {
+    pub fn sensitivity() -> Self {
+        Self::new(|widget: &W, on| widget.set_sensitive(on))
+    }
+
+    pub fn visibility() -> Self {
+        Self::new(|widget: &W, on| widget.set_visible(on))
+    }
+
+    /// Add the CSS style class `class` to the widget when the state is
+    /// `when` and remove it otherwise e.g. `style_class("error", false)`.
+    pub fn style_class(class: &str, when: bool) -> Self {
+        let class = class.to_string();
+        Self::new(move |widget: &W, on| {
+            let style_context = widget.get_style_context();
+            if on == when {
+                style_context.add_class(&class);
+            } else {
+                style_context.remove_class(&class);
+            }
+        })
+    }
+
+    pub fn tooltip_text(on_text: &str, off_text: &str) -> Self {
+        let on_text = on_text.to_string();
+        let off_text = off_text.to_string();
+        Self::new(move |widget: &W, on| {
+            if on {
+                widget.set_tooltip_text(Some(&on_text))
+            } else {
+                widget.set_tooltip_text(Some(&off_text))
+            }
+        })
+    }
+}
+
+impl<W: IsA<gtk::ToggleButton>> WidgetStateHandler<W> {
+    pub fn toggle_active() -> Self {
+        Self::new(|widget: &W, on| widget.set_active(on))
+    }
+}
+
+impl<W: IsA<gtk::Editable>> WidgetStateHandler<W> {
+    pub fn editable() -> Self {
+        Self::new(|widget: &W, on| widget.set_editable(on))
+    }
+}
+
+impl<W: IsA<gtk::Label>> WidgetStateHandler<W> {
+    pub fn label_text(on_text: &str, off_text: &str) -> Self {
+        let on_text = on_text.to_string();
+        let off_text = off_text.to_string();
+        Self::new(move |widget: &W, on| {
+            if on {
+                widget.set_text(&on_text)
+            } else {
+                widget.set_text(&off_text)
+            }
+        })
+    }
+}
+
+impl<W: IsA<gtk::Button>> WidgetStateHandler<W> {
+    pub fn button_label(on_text: &str, off_text: &str) -> Self {
+        let on_text = on_text.to_string();
+        let off_text = off_text.to_string();
+        Self::new(move |widget: &W, on| {
+            if on {
+                widget.set_label(&on_text)
+            } else {
+                widget.set_label(&off_text)
+            }
+        })
+    }
+}
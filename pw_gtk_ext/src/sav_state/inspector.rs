@@ -0,0 +1,147 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! A debugging aid that displays the conditions required by
+//! conditionally controlled widgets and which of them are missing.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use super::{ConditionalWidgets, CondnsReport, Subscription};
+use crate::wrapper::*;
+
+type CondnsReporter = Box<dyn Fn() -> Vec<CondnsReport>>;
+
+#[derive(PWO)]
+pub struct ConditionsInspectorCore {
+    v_box: gtk::Box,
+    list_store: gtk::ListStore,
+    reporters: RefCell<Vec<(String, CondnsReporter)>>,
+    subscriptions: RefCell<Vec<Subscription>>,
+}
+
+#[derive(PWO, Wrapper, WClone)]
+pub struct ConditionsInspector(Rc<ConditionsInspectorCore>);
+
+impl Default for ConditionsInspector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConditionsInspector {
+    const TITLES: [&'static str; 6] = ["Source", "Item", "Required", "Current", "Missing", "On"];
+
+    pub fn new() -> Self {
+        let v_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+        let list_store = gtk::ListStore::new(&[glib::Type::String; 6]);
+        let tree_view = gtk::TreeViewBuilder::new()
+            .model(&list_store)
+            .headers_visible(true)
+            .build();
+        for (index, title) in Self::TITLES.iter().enumerate() {
+            let col = gtk::TreeViewColumnBuilder::new()
+                .title(title)
+                .resizable(true)
+                .sort_column_id(index as i32)
+                .build();
+            let cell = gtk::CellRendererTextBuilder::new().editable(false).build();
+            col.pack_start(&cell, false);
+            col.add_attribute(&cell, "text", index as i32);
+            tree_view.append_column(&col);
+        }
+        let scrolled_window = gtk::ScrolledWindow::new(
+            Option::<&gtk::Adjustment>::None,
+            Option::<&gtk::Adjustment>::None,
+        );
+        scrolled_window.add(&tree_view);
+        v_box.pack_start(&scrolled_window, true, true, 0);
+        let refresh_button = gtk::Button::with_label("Refresh");
+        v_box.pack_start(&refresh_button, false, false, 0);
+        v_box.show_all();
+
+        let inspector = Self(Rc::new(ConditionsInspectorCore {
+            v_box,
+            list_store,
+            reporters: RefCell::new(vec![]),
+            subscriptions: RefCell::new(vec![]),
+        }));
+
+        let weak_core = Rc::downgrade(&inspector.0);
+        refresh_button.connect_clicked(move |_| {
+            if let Some(core) = weak_core.upgrade() {
+                ConditionsInspector(core).refresh()
+            }
+        });
+
+        inspector
+    }
+
+    /// Include the widgets managed by `conditional_widgets` in the
+    /// display identifying them by `label`.  The display is refreshed
+    /// whenever their change notifier reports a change.
+    ///
+    /// NB: changes made by calling `update_condns()` directly on
+    /// `conditional_widgets` (e.g. selection driven changes) don't go
+    /// through the notifier and will only be displayed after the next
+    /// notified change or a press of the "Refresh" button.
+    pub fn add_conditional_widgets<K, W>(
+        &self,
+        label: &str,
+        conditional_widgets: &ConditionalWidgets<K, W>,
+    ) where
+        W: WidgetExt + Clone + PartialEq,
+        K: Eq + std::hash::Hash + std::fmt::Debug + 'static,
+    {
+        let weak_core = Rc::downgrade(&conditional_widgets.0);
+        self.0.reporters.borrow_mut().push((
+            label.to_string(),
+            Box::new(move || match weak_core.upgrade() {
+                Some(core) => ConditionalWidgets(core).condns_report(),
+                None => vec![],
+            }),
+        ));
+        let subscription = conditional_widgets
+            .change_notifier()
+            .subscribe_weak(&self.0, |core, _| {
+                ConditionsInspector(Rc::clone(core)).refresh()
+            });
+        self.0.subscriptions.borrow_mut().push(subscription);
+        self.refresh();
+    }
+
+    pub fn refresh(&self) {
+        self.0.list_store.clear();
+        for (label, reporter) in self.0.reporters.borrow().iter() {
+            for report in reporter() {
+                let required = format!("{:#x}", report.required_condns);
+                let current = format!("{:#x}", report.current_condns);
+                let missing = format!("{:#x}", report.missing_condns());
+                let is_on = if report.is_on { "yes" } else { "no" };
+                self.0.list_store.insert_with_values(
+                    None,
+                    &[0, 1, 2, 3, 4, 5],
+                    &[
+                        label as &dyn ToValue,
+                        &report.key,
+                        &required,
+                        &current,
+                        &missing,
+                        &is_on,
+                    ],
+                );
+            }
+        }
+    }
+
+    /// Show the inspector in its own top level window.
+    pub fn show_in_window(&self, title: &str) -> gtk::Window {
+        let window = gtk::Window::new(gtk::WindowType::Toplevel);
+        window.set_title(title);
+        window.set_default_size(640, 320);
+        window.add(self.pwo());
+        window.show_all();
+        window
+    }
+}
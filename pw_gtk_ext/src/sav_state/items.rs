@@ -0,0 +1,394 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! The GUI independent core of the condition engine.  Items are
+//! grouped by the conditions that they require and each group's
+//! targets are switched on/off as those conditions (un)become
+//! satisfied.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::{CondnsReport, Error, MaskedCondns};
+
+/// Something whose state can be switched on/off by the condition engine.
+pub trait StateTarget: PartialEq {
+    fn apply_state(&self, on: bool);
+}
+
+#[derive(Debug)]
+struct ConditionalItemGroup<K, T>
+where
+    K: Eq + Hash + Debug,
+    T: StateTarget,
+{
+    items: HashMap<K, T>,
+    is_on: bool,
+}
+
+impl<K, T> ConditionalItemGroup<K, T>
+where
+    K: Eq + Hash + Debug,
+    T: StateTarget,
+{
+    fn new(is_on: bool) -> Self {
+        Self {
+            items: HashMap::new(),
+            is_on,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConditionalItems<K, T>
+where
+    K: Eq + Hash + Debug,
+    T: StateTarget,
+{
+    groups: HashMap<u64, ConditionalItemGroup<K, T>>,
+    current_condns: u64,
+    trace_label: Option<String>,
+}
+
+impl<K, T> Default for ConditionalItems<K, T>
+where
+    K: Eq + Hash + Debug,
+    T: StateTarget,
+{
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+impl<K, T> ConditionalItems<K, T>
+where
+    K: Eq + Hash + Debug,
+    T: StateTarget,
+{
+    pub fn new(initial_condns: u64) -> Self {
+        Self {
+            groups: HashMap::new(),
+            current_condns: initial_condns,
+            trace_label: None,
+        }
+    }
+
+    /// Log (at debug level) every change of conditions if `label` is
+    /// not `None`.
+    pub fn set_trace_label(&mut self, label: Option<&str>) {
+        self.trace_label = label.map(|label| label.to_string());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.values().map(|group| group.items.len()).sum()
+    }
+
+    pub fn current_condns(&self) -> u64 {
+        self.current_condns
+    }
+
+    pub fn contains_key<Q: ?Sized>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.groups
+            .values()
+            .any(|group| group.items.contains_key(key))
+    }
+
+    /// Add `target` under `key` and set its state according to whether
+    /// `condns` are currently satisfied.
+    pub fn add_item(&mut self, key: K, target: T, condns: u64) -> Result<(), Error> {
+        let (target, on) = self.insert(key, target, condns)?;
+        target.apply_state(on);
+        Ok(())
+    }
+
+    /// As `add_item()` but return the state that `target` should be set
+    /// to (rather than setting it) so that the caller can do that after
+    /// releasing any borrow of `self` (in case `target` reacts by
+    /// changing the conditions).
+    pub fn insert_item(&mut self, key: K, target: T, condns: u64) -> Result<bool, Error> {
+        self.insert(key, target, condns).map(|(_, on)| on)
+    }
+
+    fn insert(&mut self, key: K, target: T, condns: u64) -> Result<(&T, bool), Error> {
+        if self.contains_key(&key) {
+            return Err(Error::DuplicateKey);
+        }
+        if self
+            .groups
+            .values()
+            .any(|group| group.items.values().any(|item| item == &target))
+        {
+            return Err(Error::DuplicateWidget);
+        }
+        let is_on = (condns & self.current_condns) == condns;
+        let group = self
+            .groups
+            .entry(condns)
+            .or_insert_with(|| ConditionalItemGroup::new(is_on));
+        let is_on = group.is_on;
+        Ok((group.items.entry(key).or_insert(target), is_on))
+    }
+
+    pub fn get_item<Q: ?Sized>(&self, key: &Q) -> Option<&T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.groups.values().find_map(|group| group.items.get(key))
+    }
+
+    pub fn remove_item<Q: ?Sized>(&mut self, key: &Q) -> Option<T>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.groups
+            .values_mut()
+            .find_map(|group| group.items.remove(key))
+    }
+
+    /// The conditions required by the item with the given `key`.
+    pub fn item_condns<Q: ?Sized>(&self, key: &Q) -> Option<u64>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.groups
+            .iter()
+            .find(|(_, group)| group.items.contains_key(key))
+            .map(|(condns, _)| *condns)
+    }
+
+    pub fn is_on<Q: ?Sized>(&self, key: &Q) -> Option<bool>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        self.groups
+            .values()
+            .find(|group| group.items.contains_key(key))
+            .map(|group| group.is_on)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.groups.values().flat_map(|group| group.items.keys())
+    }
+
+    /// Apply `changed_condns` switching the state of only those groups
+    /// whose state has changed as a result.
+    pub fn update_condns(&mut self, changed_condns: MaskedCondns) {
+        for condns in self.apply_condns(changed_condns) {
+            let group = &self.groups[&condns];
+            for target in group.items.values() {
+                target.apply_state(group.is_on);
+            }
+        }
+    }
+
+    /// As `update_condns()` but return the targets whose state needs to
+    /// be switched (and their new states) rather than switching them so
+    /// that the caller can do that after releasing any borrow of `self`.
+    pub fn update_condns_deferred(&mut self, changed_condns: MaskedCondns) -> Vec<(T, bool)>
+    where
+        T: Clone,
+    {
+        let mut changes = vec![];
+        for condns in self.apply_condns(changed_condns) {
+            let group = &self.groups[&condns];
+            for target in group.items.values() {
+                changes.push((target.clone(), group.is_on));
+            }
+        }
+        changes
+    }
+
+    // Update the current conditions and the groups' states returning
+    // the conditions (keys) of the groups whose state changed.
+    fn apply_condns(&mut self, changed_condns: MaskedCondns) -> Vec<u64> {
+        debug_assert!(changed_condns.is_consistent());
+        let new_condns = changed_condns.condns | (self.current_condns & !changed_condns.mask);
+        if let Some(label) = &self.trace_label {
+            log::debug!(
+                "{}: update {:#x}/{:#x}: {:#x} -> {:#x}",
+                label,
+                changed_condns.condns,
+                changed_condns.mask,
+                self.current_condns,
+                new_condns
+            );
+        }
+        let mut changed = vec![];
+        for (key_condns, group) in self.groups.iter_mut() {
+            if changed_condns.mask & key_condns != 0 {
+                let on = (key_condns & new_condns) == *key_condns;
+                if on == group.is_on {
+                    continue;
+                }
+                if let Some(label) = &self.trace_label {
+                    log::trace!(
+                        "{}: {:?} {} (requires {:#x}, missing {:#x})",
+                        label,
+                        group.items.keys().collect::<Vec<_>>(),
+                        if on { "on" } else { "off" },
+                        key_condns,
+                        key_condns & !new_condns
+                    );
+                }
+                group.is_on = on;
+                changed.push(*key_condns);
+            };
+        }
+        self.current_condns = new_condns;
+        changed
+    }
+
+    /// Describe the conditions required by each of the items and
+    /// whether they are currently satisfied.
+    pub fn condns_report(&self) -> Vec<CondnsReport> {
+        let mut report = vec![];
+        for (required_condns, group) in self.groups.iter() {
+            for key in group.items.keys() {
+                report.push(CondnsReport {
+                    key: format!("{:?}", key),
+                    required_condns: *required_condns,
+                    current_condns: self.current_condns,
+                    is_on: group.is_on,
+                });
+            }
+        }
+        report.sort_by(|a, b| a.key.cmp(&b.key));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone)]
+    struct MockTarget {
+        id: u32,
+        state: Rc<Cell<bool>>,
+        n_changes: Rc<Cell<u32>>,
+    }
+
+    impl MockTarget {
+        fn new(id: u32) -> Self {
+            Self {
+                id,
+                state: Rc::new(Cell::new(false)),
+                n_changes: Rc::new(Cell::new(0)),
+            }
+        }
+    }
+
+    impl PartialEq for MockTarget {
+        fn eq(&self, other: &Self) -> bool {
+            self.id == other.id
+        }
+    }
+
+    impl StateTarget for MockTarget {
+        fn apply_state(&self, on: bool) {
+            self.state.set(on);
+            self.n_changes.set(self.n_changes.get() + 1);
+        }
+    }
+
+    const A: u64 = 1;
+    const B: u64 = 1 << 1;
+    const C: u64 = 1 << 2;
+
+    #[test]
+    fn items_follow_condns() {
+        let mut items = ConditionalItems::<&str, MockTarget>::new(A);
+        let (t1, t2, t3) = (MockTarget::new(1), MockTarget::new(2), MockTarget::new(3));
+        items.add_item("one", t1.clone(), A).unwrap();
+        items.add_item("two", t2.clone(), A | B).unwrap();
+        items.add_item("three", t3.clone(), C).unwrap();
+        assert_eq!(items.len(), 3);
+        assert!(t1.state.get());
+        assert!(!t2.state.get());
+        assert!(!t3.state.get());
+
+        items.update_condns(MaskedCondns {
+            condns: B,
+            mask: B | C,
+        });
+        assert!(t1.state.get());
+        assert!(t2.state.get());
+        assert!(!t3.state.get());
+        assert_eq!(items.current_condns(), A | B);
+        assert_eq!(items.item_condns("two"), Some(A | B));
+        assert_eq!(items.is_on("three"), Some(false));
+
+        let report = items.condns_report();
+        let three = report.iter().find(|r| r.key == "\"three\"").unwrap();
+        assert_eq!(three.missing_condns(), C);
+    }
+
+    #[test]
+    fn only_changed_groups_are_touched() {
+        let mut items = ConditionalItems::<u32, MockTarget>::new(0);
+        let (t1, t2) = (MockTarget::new(1), MockTarget::new(2));
+        items.add_item(1, t1.clone(), A).unwrap();
+        items.add_item(2, t2.clone(), B).unwrap();
+        let (c1, c2) = (t1.n_changes.get(), t2.n_changes.get());
+        items.update_condns(MaskedCondns {
+            condns: A,
+            mask: A | B,
+        });
+        assert_eq!(t1.n_changes.get(), c1 + 1);
+        assert_eq!(t2.n_changes.get(), c2);
+    }
+
+    #[test]
+    fn duplicates_are_rejected() {
+        let mut items = ConditionalItems::<&str, MockTarget>::default();
+        items.add_item("one", MockTarget::new(1), A).unwrap();
+        assert!(matches!(
+            items.add_item("one", MockTarget::new(2), B),
+            Err(Error::DuplicateKey)
+        ));
+        assert!(matches!(
+            items.add_item("two", MockTarget::new(1), A),
+            Err(Error::DuplicateWidget)
+        ));
+        assert!(matches!(
+            items.add_item("three", MockTarget::new(1), C),
+            Err(Error::DuplicateWidget)
+        ));
+        assert_eq!(items.item_condns("one"), Some(A));
+        assert!(items.groups.get(&C).is_none());
+        assert!(items.remove_item("one").is_some());
+        assert!(items.get_item("one").is_none());
+        assert!(items.remove_item("one").is_none());
+    }
+
+    #[test]
+    fn deferred_updates_leave_targets_untouched() {
+        let mut items = ConditionalItems::<u32, MockTarget>::new(0);
+        let t1 = MockTarget::new(1);
+        let on = items.insert_item(1, t1.clone(), A).unwrap();
+        assert!(!on);
+        assert_eq!(t1.n_changes.get(), 0);
+        let changes = items.update_condns_deferred(MaskedCondns { condns: A, mask: A });
+        assert_eq!(t1.n_changes.get(), 0);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].0, t1);
+        assert!(changes[0].1);
+        assert_eq!(items.is_on(&1), Some(true));
+        assert_eq!(items.current_condns(), A);
+    }
+}
@@ -0,0 +1,509 @@
+// Copyright 2021 Peter Williams <pwil3058@gmail.com> <pwil3058@bigpond.net.au>
+
+//! Ready made condition sources for common widgets and objects.
+//! Each source occupies a contiguous range of condition bits starting
+//! at the (single bit) `first_condn` supplied on creation and, when
+//! connected, keeps a `ChangedCondnsNotifier` informed of changes.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use super::{ChangedCondnsNotifier, MaskedCondns};
+
+// Whether `num_condns` conditions starting at `first_condn` fit in a u64
+fn condns_fit(first_condn: u64, num_condns: u32) -> bool {
+    first_condn.is_power_of_two() && num_condns <= first_condn.leading_zeros() + 1
+}
+
+fn nth_condn(first_condn: u64, n: u32) -> u64 {
+    debug_assert!(first_condn.is_power_of_two());
+    debug_assert!(n <= first_condn.leading_zeros());
+    first_condn << n
+}
+
+// The condition following the block of `num_condns` conditions starting
+// at `first_condn` (zero if the block ends at the top bit).
+fn next_condn(first_condn: u64, num_condns: u32) -> u64 {
+    debug_assert!(condns_fit(first_condn, num_condns));
+    if num_condns > first_condn.leading_zeros() {
+        0
+    } else {
+        first_condn << num_condns
+    }
+}
+
+fn condns_mask(first_condn: u64, num_condns: u32) -> u64 {
+    debug_assert!(condns_fit(first_condn, num_condns));
+    if num_condns == 0 {
+        0
+    } else {
+        (u64::MAX >> (64 - num_condns)) << first_condn.trailing_zeros()
+    }
+}
+
+fn one_of(condition: bool, if_true: u64, if_false: u64) -> u64 {
+    if condition {
+        if_true
+    } else {
+        if_false
+    }
+}
+
+/// Conditions describing the state of a `gtk::TextBuffer` or, when
+/// connected via `connect_source_buffer()`, a `sourceview::Buffer`.
+/// NB: plain text buffers can't undo so the `can_undo()`/`cannot_undo()`
+/// conditions are only set for buffers connected via
+/// `connect_source_buffer()` and are left untouched by `connect()`.
+#[derive(Debug, Clone, Copy)]
+pub struct TextBufferCondns {
+    first_condn: u64,
+}
+
+impl TextBufferCondns {
+    pub const NUM_CONDNS: u32 = 6;
+
+    pub fn new(first_condn: u64) -> Self {
+        debug_assert!(condns_fit(first_condn, Self::NUM_CONDNS));
+        Self { first_condn }
+    }
+
+    pub fn has_selection(&self) -> u64 {
+        nth_condn(self.first_condn, 0)
+    }
+
+    pub fn no_selection(&self) -> u64 {
+        nth_condn(self.first_condn, 1)
+    }
+
+    pub fn modified(&self) -> u64 {
+        nth_condn(self.first_condn, 2)
+    }
+
+    pub fn unmodified(&self) -> u64 {
+        nth_condn(self.first_condn, 3)
+    }
+
+    pub fn can_undo(&self) -> u64 {
+        nth_condn(self.first_condn, 4)
+    }
+
+    pub fn cannot_undo(&self) -> u64 {
+        nth_condn(self.first_condn, 5)
+    }
+
+    pub fn mask(&self) -> u64 {
+        condns_mask(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn next_condn(&self) -> u64 {
+        next_condn(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn masked_condns<B: IsA<gtk::TextBuffer>>(&self, buffer: &B) -> MaskedCondns {
+        let condns = one_of(
+            buffer.get_has_selection(),
+            self.has_selection(),
+            self.no_selection(),
+        ) | one_of(buffer.get_modified(), self.modified(), self.unmodified());
+        MaskedCondns {
+            condns,
+            mask: self.has_selection() | self.no_selection() | self.modified() | self.unmodified(),
+        }
+    }
+
+    pub fn source_buffer_masked_condns(&self, buffer: &sourceview::Buffer) -> MaskedCondns {
+        use sourceview::BufferExt;
+        let undo = MaskedCondns {
+            condns: one_of(buffer.can_undo(), self.can_undo(), self.cannot_undo()),
+            mask: self.can_undo() | self.cannot_undo(),
+        };
+        self.masked_condns(buffer) | undo
+    }
+
+    /// Notify `change_notifier` of `buffer`'s current state and whenever
+    /// its selection or modification state changes.  The undo conditions
+    /// are not included (see `connect_source_buffer()`).
+    pub fn connect<B: IsA<gtk::TextBuffer>>(
+        &self,
+        buffer: &B,
+        change_notifier: &ChangedCondnsNotifier,
+    ) {
+        change_notifier.notify_changed_condns(self.masked_condns(buffer));
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        buffer.connect_modified_changed(move |buffer| {
+            change_notifier_c.notify_changed_condns(condns.masked_condns(buffer))
+        });
+        let change_notifier_c = change_notifier.clone();
+        buffer.connect_property_has_selection_notify(move |buffer| {
+            change_notifier_c.notify_changed_condns(condns.masked_condns(buffer))
+        });
+    }
+
+    /// As for `connect()` but also tracks the buffer's ability to undo.
+    pub fn connect_source_buffer(
+        &self,
+        buffer: &sourceview::Buffer,
+        change_notifier: &ChangedCondnsNotifier,
+    ) {
+        use sourceview::BufferExt;
+        change_notifier.notify_changed_condns(self.source_buffer_masked_condns(buffer));
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        buffer.connect_modified_changed(move |buffer| {
+            change_notifier_c.notify_changed_condns(condns.source_buffer_masked_condns(buffer))
+        });
+        let change_notifier_c = change_notifier.clone();
+        buffer.connect_property_has_selection_notify(move |buffer| {
+            change_notifier_c.notify_changed_condns(condns.source_buffer_masked_condns(buffer))
+        });
+        let change_notifier_c = change_notifier.clone();
+        buffer.connect_property_can_undo_notify(move |buffer| {
+            change_notifier_c.notify_changed_condns(condns.source_buffer_masked_condns(buffer))
+        });
+    }
+}
+
+pub type EntryValidator = Rc<dyn Fn(&str) -> bool>;
+
+/// Conditions describing the state of a `gtk::Entry`'s text.
+#[derive(Debug, Clone, Copy)]
+pub struct EntryCondns {
+    first_condn: u64,
+}
+
+impl EntryCondns {
+    pub const NUM_CONDNS: u32 = 4;
+
+    pub fn new(first_condn: u64) -> Self {
+        debug_assert!(condns_fit(first_condn, Self::NUM_CONDNS));
+        Self { first_condn }
+    }
+
+    pub fn empty(&self) -> u64 {
+        nth_condn(self.first_condn, 0)
+    }
+
+    pub fn not_empty(&self) -> u64 {
+        nth_condn(self.first_condn, 1)
+    }
+
+    pub fn valid(&self) -> u64 {
+        nth_condn(self.first_condn, 2)
+    }
+
+    pub fn invalid(&self) -> u64 {
+        nth_condn(self.first_condn, 3)
+    }
+
+    pub fn mask(&self) -> u64 {
+        condns_mask(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn next_condn(&self) -> u64 {
+        next_condn(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    /// If no `validator` is supplied the validity conditions are not
+    /// included in the mask.
+    pub fn masked_condns<E: IsA<gtk::Entry>>(
+        &self,
+        entry: &E,
+        validator: Option<&EntryValidator>,
+    ) -> MaskedCondns {
+        let text = entry.get_text();
+        let mut masked_condns = MaskedCondns {
+            condns: one_of(text.is_empty(), self.empty(), self.not_empty()),
+            mask: self.empty() | self.not_empty(),
+        };
+        if let Some(validator) = validator {
+            masked_condns = masked_condns
+                | MaskedCondns {
+                    condns: one_of(validator(text.as_str()), self.valid(), self.invalid()),
+                    mask: self.valid() | self.invalid(),
+                };
+        }
+        masked_condns
+    }
+
+    /// Notify `change_notifier` of `entry`'s current state and whenever
+    /// its text changes.
+    pub fn connect<E: IsA<gtk::Entry> + IsA<gtk::Editable>>(
+        &self,
+        entry: &E,
+        change_notifier: &ChangedCondnsNotifier,
+    ) {
+        self.connect_with_validator(entry, change_notifier, None)
+    }
+
+    pub fn connect_with_validator<E: IsA<gtk::Entry> + IsA<gtk::Editable>>(
+        &self,
+        entry: &E,
+        change_notifier: &ChangedCondnsNotifier,
+        validator: Option<EntryValidator>,
+    ) {
+        change_notifier.notify_changed_condns(self.masked_condns(entry, validator.as_ref()));
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        entry.connect_changed(move |entry| {
+            change_notifier_c.notify_changed_condns(condns.masked_condns(entry, validator.as_ref()))
+        });
+    }
+}
+
+/// Conditions describing whether a `gtk::ToggleButton` is active.
+#[derive(Debug, Clone, Copy)]
+pub struct ToggleButtonCondns {
+    first_condn: u64,
+}
+
+impl ToggleButtonCondns {
+    pub const NUM_CONDNS: u32 = 2;
+
+    pub fn new(first_condn: u64) -> Self {
+        debug_assert!(condns_fit(first_condn, Self::NUM_CONDNS));
+        Self { first_condn }
+    }
+
+    pub fn active(&self) -> u64 {
+        nth_condn(self.first_condn, 0)
+    }
+
+    pub fn inactive(&self) -> u64 {
+        nth_condn(self.first_condn, 1)
+    }
+
+    pub fn mask(&self) -> u64 {
+        condns_mask(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn next_condn(&self) -> u64 {
+        next_condn(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn masked_condns<T: IsA<gtk::ToggleButton>>(&self, toggle_button: &T) -> MaskedCondns {
+        MaskedCondns {
+            condns: one_of(toggle_button.get_active(), self.active(), self.inactive()),
+            mask: self.mask(),
+        }
+    }
+
+    /// Notify `change_notifier` of `toggle_button`'s current state and
+    /// whenever it is toggled.
+    pub fn connect<T: IsA<gtk::ToggleButton>>(
+        &self,
+        toggle_button: &T,
+        change_notifier: &ChangedCondnsNotifier,
+    ) {
+        change_notifier.notify_changed_condns(self.masked_condns(toggle_button));
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        toggle_button.connect_toggled(move |toggle_button| {
+            change_notifier_c.notify_changed_condns(condns.masked_condns(toggle_button))
+        });
+    }
+}
+
+/// Conditions describing which page of a `gtk::Notebook` is current.
+/// One condition is allocated for each of the first `num_pages` pages.
+#[derive(Debug, Clone, Copy)]
+pub struct NotebookCondns {
+    first_condn: u64,
+    num_pages: u32,
+}
+
+impl NotebookCondns {
+    pub fn new(first_condn: u64, num_pages: u32) -> Self {
+        debug_assert!(condns_fit(first_condn, num_pages));
+        Self {
+            first_condn,
+            num_pages,
+        }
+    }
+
+    pub fn num_condns(&self) -> u32 {
+        self.num_pages
+    }
+
+    pub fn page(&self, page_num: u32) -> u64 {
+        debug_assert!(page_num < self.num_pages);
+        nth_condn(self.first_condn, page_num)
+    }
+
+    pub fn mask(&self) -> u64 {
+        condns_mask(self.first_condn, self.num_pages)
+    }
+
+    pub fn next_condn(&self) -> u64 {
+        next_condn(self.first_condn, self.num_pages)
+    }
+
+    pub fn masked_condns_for_page(&self, page_num: Option<u32>) -> MaskedCondns {
+        let condns = match page_num {
+            Some(page_num) if page_num < self.num_pages => self.page(page_num),
+            _ => 0,
+        };
+        MaskedCondns {
+            condns,
+            mask: self.mask(),
+        }
+    }
+
+    pub fn masked_condns<N: IsA<gtk::Notebook>>(&self, notebook: &N) -> MaskedCondns {
+        self.masked_condns_for_page(notebook.get_current_page())
+    }
+
+    /// Notify `change_notifier` of `notebook`'s current page and whenever
+    /// the current page changes.
+    pub fn connect<N: IsA<gtk::Notebook>>(
+        &self,
+        notebook: &N,
+        change_notifier: &ChangedCondnsNotifier,
+    ) {
+        change_notifier.notify_changed_condns(self.masked_condns(notebook));
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        // NB: the current page hasn't been updated when this signal is emitted
+        notebook.connect_switch_page(move |_, _, page_num| {
+            change_notifier_c.notify_changed_condns(condns.masked_condns_for_page(Some(page_num)))
+        });
+    }
+}
+
+/// Conditions describing the contents of a `gtk::Clipboard`.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipboardCondns {
+    first_condn: u64,
+}
+
+impl ClipboardCondns {
+    pub const NUM_CONDNS: u32 = 4;
+
+    pub fn new(first_condn: u64) -> Self {
+        debug_assert!(condns_fit(first_condn, Self::NUM_CONDNS));
+        Self { first_condn }
+    }
+
+    pub fn has_text(&self) -> u64 {
+        nth_condn(self.first_condn, 0)
+    }
+
+    pub fn no_text(&self) -> u64 {
+        nth_condn(self.first_condn, 1)
+    }
+
+    pub fn has_image(&self) -> u64 {
+        nth_condn(self.first_condn, 2)
+    }
+
+    pub fn no_image(&self) -> u64 {
+        nth_condn(self.first_condn, 3)
+    }
+
+    pub fn mask(&self) -> u64 {
+        condns_mask(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    pub fn next_condn(&self) -> u64 {
+        next_condn(self.first_condn, Self::NUM_CONDNS)
+    }
+
+    /// The conditions for a clipboard whose "TARGETS" are `targets`.
+    pub fn masked_condns_for_targets(&self, targets: &gtk::SelectionData) -> MaskedCondns {
+        let condns = one_of(
+            targets.targets_include_text(),
+            self.has_text(),
+            self.no_text(),
+        ) | one_of(
+            targets.targets_include_image(false),
+            self.has_image(),
+            self.no_image(),
+        );
+        MaskedCondns {
+            condns,
+            mask: self.mask(),
+        }
+    }
+
+    /// Notify `change_notifier` of `clipboard`'s current contents and
+    /// whenever its owner changes.  The clipboard's targets are requested
+    /// asynchronously so that a slow clipboard owner can't block the UI.
+    pub fn connect(&self, clipboard: &gtk::Clipboard, change_notifier: &ChangedCondnsNotifier) {
+        let last_request = Rc::new(Cell::new(0u64));
+        self.request_update(clipboard, change_notifier, &last_request);
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        clipboard.connect_owner_change(move |clipboard, _| {
+            condns.request_update(clipboard, &change_notifier_c, &last_request)
+        });
+    }
+
+    // NB: replies may arrive out of order so those to requests that have
+    // been superseded (by a later owner change) are ignored
+    fn request_update(
+        &self,
+        clipboard: &gtk::Clipboard,
+        change_notifier: &ChangedCondnsNotifier,
+        last_request: &Rc<Cell<u64>>,
+    ) {
+        let request = last_request.get().wrapping_add(1);
+        last_request.set(request);
+        let condns = *self;
+        let change_notifier_c = change_notifier.clone();
+        let last_request_c = Rc::clone(last_request);
+        let targets_atom = gdk::Atom::intern("TARGETS");
+        clipboard.request_contents(&targets_atom, move |_, targets| {
+            if last_request_c.get() == request {
+                change_notifier_c.notify_changed_condns(condns.masked_condns_for_targets(targets))
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nth_condn_shifts_first_condn() {
+        assert_eq!(nth_condn(1, 0), 1);
+        assert_eq!(nth_condn(1 << 4, 3), 1 << 7);
+        assert_eq!(nth_condn(1 << 60, 3), 1 << 63);
+    }
+
+    #[test]
+    fn condns_mask_covers_block() {
+        assert_eq!(condns_mask(1 << 4, 0), 0);
+        assert_eq!(condns_mask(1 << 4, 3), 0b111 << 4);
+        assert_eq!(condns_mask(1, 64), u64::MAX);
+        assert_eq!(condns_mask(1 << 60, 4), 0xF << 60);
+    }
+
+    #[test]
+    fn blocks_may_end_at_top_bit() {
+        assert!(condns_fit(1 << 60, 4));
+        assert!(!condns_fit(1 << 60, 5));
+        assert!(!condns_fit(3, 1));
+        assert_eq!(next_condn(1 << 60, 3), 1 << 63);
+        assert_eq!(next_condn(1 << 60, 4), 0);
+        let clipboard_condns = ClipboardCondns::new(1 << 60);
+        assert_eq!(clipboard_condns.no_image(), 1 << 63);
+        assert_eq!(clipboard_condns.mask(), 0xF << 60);
+        assert_eq!(clipboard_condns.next_condn(), 0);
+    }
+
+    #[test]
+    fn notebook_condns_allocation() {
+        let notebook_condns = NotebookCondns::new(1 << 8, 3);
+        assert_eq!(notebook_condns.page(2), 1 << 10);
+        assert_eq!(notebook_condns.mask(), 0b111 << 8);
+        assert_eq!(notebook_condns.next_condn(), 1 << 11);
+        let masked_condns = notebook_condns.masked_condns_for_page(Some(1));
+        assert_eq!(masked_condns.condns, 1 << 9);
+        assert_eq!(masked_condns.mask, 0b111 << 8);
+        let masked_condns = notebook_condns.masked_condns_for_page(None);
+        assert_eq!(masked_condns.condns, 0);
+    }
+}
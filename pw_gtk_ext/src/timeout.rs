@@ -3,9 +3,9 @@
 use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
-use gtk::{self, CheckMenuItemExt};
+use crate::gtk::prelude::*;
 
-pub type NumberedCallbacks = Vec<(u32, Box<dyn Fn()>)>;
+pub type NumberedCallbacks = Vec<(u32, Rc<dyn Fn()>)>;
 
 pub struct ControlledTimeoutCycle {
     interval_secs: Cell<u32>,
@@ -31,9 +31,7 @@ impl ControlledTimeoutCycle {
                 let interval_secs = ct_clone.interval_secs.get();
                 let ct_clone_clone = Rc::clone(&ct_clone);
                 glib::source::timeout_add_seconds_local(interval_secs, move || {
-                    for (_, callback) in ct_clone_clone.callbacks.borrow().iter() {
-                        callback()
-                    }
+                    ct_clone_clone.invoke_callbacks();
                     ct_clone_clone
                         .stopped
                         .set(!ct_clone_clone.check_menu_item.get_active());
@@ -53,6 +51,10 @@ impl ControlledTimeoutCycle {
         self.check_menu_item.clone()
     }
 
+    pub fn is_active(&self) -> bool {
+        self.check_menu_item.get_active()
+    }
+
     pub fn set_interval_secs(&self, interval_secs: u32) {
         self.interval_secs.set(interval_secs);
     }
@@ -64,7 +66,33 @@ impl ControlledTimeoutCycle {
     pub fn register_callback(&self, callback: Box<dyn Fn()>) -> u32 {
         let cb_id = self.next_cb_id.get();
         self.next_cb_id.set(cb_id + 1);
-        self.callbacks.borrow_mut().push((cb_id, callback));
+        self.callbacks
+            .borrow_mut()
+            .push((cb_id, Rc::from(callback)));
         cb_id
     }
+
+    pub fn unregister_callback(&self, cb_id: u32) {
+        self.callbacks.borrow_mut().retain(|(id, _)| *id != cb_id);
+    }
+
+    /// Call the registered callbacks now rather than waiting for the
+    /// timeout (e.g. in response to a "Refresh" button).
+    pub fn invoke_callbacks(&self) {
+        // NB: callbacks may (indirectly) unregister callbacks
+        let callbacks: Vec<Rc<dyn Fn()>> = self
+            .callbacks
+            .borrow()
+            .iter()
+            .map(|(_, callback)| Rc::clone(callback))
+            .collect();
+        for callback in callbacks {
+            callback()
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn callback_count(&self) -> usize {
+        self.callbacks.borrow().len()
+    }
 }
@@ -138,3 +138,282 @@ pub fn wclone_derive(input: TokenStream) -> TokenStream {
 
     proc_macro::TokenStream::from(tokens)
 }
+
+struct ListRowField {
+    ident: syn::Ident,
+    ty: syn::Type,
+    title: String,
+    sortable: bool,
+    resizable: bool,
+    hidden: bool,
+    renderer: String,
+}
+
+impl ListRowField {
+    fn new(field: &syn::Field) -> Result<Self, syn::Error> {
+        let ident = match &field.ident {
+            Some(ident) => ident.clone(),
+            None => return Err(syn::Error::new_spanned(field, "named field required")),
+        };
+        let mut list_row_field = Self {
+            title: ident.to_string(),
+            ident,
+            ty: field.ty.clone(),
+            sortable: false,
+            resizable: false,
+            hidden: false,
+            renderer: "text".to_string(),
+        };
+        for attr in field.attrs.iter().filter(|a| a.path.is_ident("list_row")) {
+            let meta_list = match attr.parse_meta()? {
+                syn::Meta::List(meta_list) => meta_list,
+                meta => return Err(syn::Error::new_spanned(meta, "expected list_row(...)")),
+            };
+            for nested in meta_list.nested.iter() {
+                match nested {
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("sortable") => {
+                        list_row_field.sortable = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("resizable") => {
+                        list_row_field.resizable = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::Path(path)) if path.is_ident("hidden") => {
+                        list_row_field.hidden = true
+                    }
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: syn::Lit::Str(lit),
+                        ..
+                    })) if path.is_ident("title") => list_row_field.title = lit.value(),
+                    syn::NestedMeta::Meta(syn::Meta::NameValue(syn::MetaNameValue {
+                        path,
+                        lit: syn::Lit::Str(lit),
+                        ..
+                    })) if path.is_ident("renderer") => match lit.value().as_str() {
+                        "text" | "markup" | "toggle" | "pixbuf" => {
+                            list_row_field.renderer = lit.value()
+                        }
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                lit,
+                                "expected \"text\", \"markup\", \"toggle\" or \"pixbuf\"",
+                            ))
+                        }
+                    },
+                    _ => return Err(syn::Error::new_spanned(nested, "unknown list_row option")),
+                }
+            }
+        }
+        Ok(list_row_field)
+    }
+
+    fn column_tokens(&self, index: i32) -> proc_macro2::TokenStream {
+        let title = &self.title;
+        let resizable = self.resizable;
+        let sort_tokens = if self.sortable {
+            quote! {
+                col.set_sort_column_id(#index);
+                col.set_sort_indicator(true);
+            }
+        } else {
+            quote! {}
+        };
+        let (cell_tokens, attribute) = match self.renderer.as_str() {
+            "toggle" => (
+                quote! { gtk::CellRendererToggleBuilder::new().activatable(false).build() },
+                "active",
+            ),
+            "pixbuf" => (quote! { gtk::CellRendererPixbuf::new() }, "pixbuf"),
+            "markup" => (
+                quote! { gtk::CellRendererTextBuilder::new().editable(false).build() },
+                "markup",
+            ),
+            _ => (
+                quote! { gtk::CellRendererTextBuilder::new().editable(false).build() },
+                "text",
+            ),
+        };
+        quote! {
+            let col = gtk::TreeViewColumnBuilder::new()
+                .title(#title)
+                .resizable(#resizable)
+                .build();
+            #sort_tokens
+            let cell = #cell_tokens;
+            col.pack_start(&cell, false);
+            col.add_attribute(&cell, #attribute, #index);
+            cols.push(col);
+        }
+    }
+}
+
+/// Implement `ListRow` and `ListViewSpec` for a struct with named fields
+/// each of which becomes a column in the list store (in field order).
+/// Columns can be configured with e.g. `#[list_row(title = "Name",
+/// sortable, resizable)]`, `#[list_row(renderer = "toggle")]` (one of
+/// "text", "markup", "toggle" or "pixbuf") or `#[list_row(hidden)]`.
+#[proc_macro_derive(ListRow, attributes(list_row))]
+pub fn list_row_derive(input: TokenStream) -> TokenStream {
+    let parsed_input: syn::DeriveInput = syn::parse_macro_input!(input);
+    proc_macro::TokenStream::from(list_row_tokens(&parsed_input))
+}
+
+// NB: separated from the derive so that the expansion can be tested
+fn list_row_tokens(parsed_input: &syn::DeriveInput) -> proc_macro2::TokenStream {
+    let struct_name = &parsed_input.ident;
+    let named_fields = match &parsed_input.data {
+        syn::Data::Struct(syn::DataStruct {
+            fields: syn::Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return quote_spanned! {
+                struct_name.span()=> compile_error!("'ListRow' requires a struct with named fields");
+            }
+        }
+    };
+    let fields = match named_fields
+        .iter()
+        .map(ListRowField::new)
+        .collect::<Result<Vec<_>, _>>()
+    {
+        Ok(fields) => fields,
+        Err(err) => return err.to_compile_error(),
+    };
+    let types: Vec<&syn::Type> = fields.iter().map(|f| &f.ty).collect();
+    let idents: Vec<&syn::Ident> = fields.iter().map(|f| &f.ident).collect();
+    let indices: Vec<usize> = (0..fields.len()).collect();
+    let columns: Vec<proc_macro2::TokenStream> = fields
+        .iter()
+        .enumerate()
+        .filter(|(_, f)| !f.hidden)
+        .map(|(index, f)| f.column_tokens(index as i32))
+        .collect();
+    let (impl_generics, ty_generics, where_clause) = parsed_input.generics.split_for_impl();
+
+    let tokens = quote! {
+        impl #impl_generics ListRow for #struct_name #ty_generics #where_clause {
+            fn column_types() -> Vec<glib::Type> {
+                vec![#(<#types as glib::StaticType>::static_type()),*]
+            }
+
+            fn columns() -> Vec<gtk::TreeViewColumn> {
+                use gtk::prelude::*;
+                let mut cols = vec![];
+                #(#columns)*
+                cols
+            }
+
+            fn to_row(&self) -> Vec<glib::Value> {
+                vec![#(glib::ToValue::to_value(&self.#idents)),*]
+            }
+
+            fn from_row(row: &[glib::Value]) -> Option<Self> {
+                Some(Self {
+                    #(#idents: row.get(#indices)?.get::<#types>().ok().flatten()?),*
+                })
+            }
+        }
+
+        impl #impl_generics ListViewSpec for #struct_name #ty_generics #where_clause {
+            fn column_types() -> Vec<glib::Type> {
+                <Self as ListRow>::column_types()
+            }
+
+            fn columns() -> Vec<gtk::TreeViewColumn> {
+                <Self as ListRow>::columns()
+            }
+        }
+    };
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compare expansions without worrying about the spacing
+    fn expand(input: syn::DeriveInput) -> String {
+        list_row_tokens(&input)
+            .to_string()
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect()
+    }
+
+    #[test]
+    fn fields_become_columns_in_order() {
+        let expansion = expand(syn::parse_quote! {
+            struct Row {
+                #[list_row(title = "Name", sortable, resizable)]
+                name: String,
+                #[list_row(renderer = "toggle")]
+                enabled: bool,
+                #[list_row(renderer = "markup")]
+                description: String,
+                #[list_row(hidden)]
+                id: u32,
+            }
+        });
+        assert!(expansion.contains(
+            "vec![<Stringasglib::StaticType>::static_type(),<boolasglib::StaticType>::static_type(),<Stringasglib::StaticType>::static_type(),<u32asglib::StaticType>::static_type()]"
+        ));
+        assert!(expansion.contains(".title(\"Name\").resizable(true)"));
+        assert!(expansion.contains("col.set_sort_column_id(0i32)"));
+        assert!(expansion.contains("col.add_attribute(&cell,\"active\",1i32)"));
+        assert!(expansion.contains("col.add_attribute(&cell,\"markup\",2i32)"));
+        assert!(!expansion.contains("3i32"));
+        assert!(expansion.contains("id:row.get(3usize)?.get::<u32>().ok().flatten()?"));
+        assert!(expansion.contains("implListViewSpecforRow"));
+    }
+
+    #[test]
+    fn generics_are_passed_through() {
+        let expansion = expand(syn::parse_quote! {
+            struct Row<T: glib::StaticType> {
+                value: T,
+            }
+        });
+        assert!(expansion.contains("impl<T:glib::StaticType>ListRowforRow<T>"));
+    }
+
+    #[test]
+    fn unsupported_inputs_are_reported() {
+        let expansion = expand(syn::parse_quote! {
+            struct Row(String, bool);
+        });
+        assert!(expansion.contains("compile_error!(\"'ListRow'requiresastructwithnamedfields\")"));
+        let expansion = expand(syn::parse_quote! {
+            enum Row {
+                One,
+            }
+        });
+        assert!(expansion.contains("requiresastructwithnamedfields"));
+    }
+
+    #[test]
+    fn invalid_options_are_reported() {
+        let expansion = expand(syn::parse_quote! {
+            struct Row {
+                #[list_row(editable)]
+                name: String,
+            }
+        });
+        assert!(expansion.contains("compile_error!"));
+        assert!(expansion.contains("\"unknownlist_rowoption\""));
+        let expansion = expand(syn::parse_quote! {
+            struct Row {
+                #[list_row(renderer = "spinner")]
+                name: String,
+            }
+        });
+        assert!(expansion.contains("expected\\\"text\\\""));
+        let expansion = expand(syn::parse_quote! {
+            struct Row {
+                #[list_row = "Name"]
+                name: String,
+            }
+        });
+        assert!(expansion.contains("\"expectedlist_row(...)\""));
+    }
+}